@@ -0,0 +1,134 @@
+// The water material, see `WaterMaterial` in src/voxel/water.rs.
+
+#import bevy_pbr::{
+    mesh_functions::{get_model_matrix, mesh_position_local_to_world, mesh_normal_local_to_world},
+    mesh_view_bindings::{view, lights, globals, fog},
+    mesh_view_types::{FOG_MODE_LINEAR, FOG_MODE_EXPONENTIAL, FOG_MODE_EXPONENTIAL_SQUARED, FOG_MODE_ATMOSPHERIC},
+    view_transformations::position_world_to_clip,
+    fog::{linear_fog, exponential_fog, exponential_squared_fog, atmospheric_fog},
+}
+
+#ifdef TONEMAP_IN_SHADER
+#import bevy_core_pipeline::tonemapping::tone_mapping
+#endif
+
+@group(2) @binding(0) var<uniform> shallow_color: vec4<f32>;
+@group(2) @binding(1) var<uniform> deep_color: vec4<f32>;
+@group(2) @binding(2) var<uniform> deep_depth: f32;
+@group(2) @binding(3) var<uniform> flow_speed: f32;
+@group(2) @binding(4) var<uniform> wave_strength: f32;
+@group(2) @binding(5) var<uniform> wave_speed: f32;
+@group(2) @binding(6) var<uniform> tile_size: vec2<f32>;
+@group(2) @binding(7) var texture: texture_2d<f32>;
+@group(2) @binding(8) var texture_sampler: sampler;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) flow: vec3<f32>,
+    @location(4) depth: f32,
+    @location(5) tile: vec2<f32>,
+    @location(6) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) flow: vec3<f32>,
+    @location(4) depth: f32,
+    @location(5) tile: vec2<f32>,
+    @location(6) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let world_position = mesh_position_local_to_world(get_model_matrix(vertex.instance_index), vec4(vertex.position, 1.0));
+    out.clip_position = position_world_to_clip(world_position.xyz);
+    out.world_position = world_position.xyz;
+    out.world_normal = mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+    out.uv = vertex.uv;
+    out.flow = vertex.flow;
+    out.depth = vertex.depth;
+    out.tile = vertex.tile;
+    out.color = vertex.color;
+    return out;
+}
+
+// Moves UVs, counted in tiles, by `offset` tiles and wraps them around inside
+// the atlas tile starting at `tile`, instead of running into the tile next to it.
+fn scroll_in_tile(uv: vec2<f32>, tile: vec2<f32>, offset: vec2<f32>) -> vec2<f32> {
+    return tile + fract(uv + offset) * tile_size;
+}
+
+// The flow as it looks on a face: along x and z on top, sideways and down on the sides.
+// Tiles on side faces are upright, so v goes down.
+fn face_flow(normal: vec3<f32>, flow: vec3<f32>) -> vec2<f32> {
+    if abs(normal.y) > 0.5 {
+        return flow.xz;
+    }
+    if abs(normal.x) > 0.5 {
+        return vec2(flow.z, -flow.y);
+    }
+    return vec2(flow.x, -flow.y);
+}
+
+// Ripples made of a few sines, drifting along with the flow.
+fn wave_normal(normal: vec3<f32>, world_position: vec3<f32>, flow: vec3<f32>) -> vec3<f32> {
+    let time = globals.time * wave_speed;
+    let p = world_position.xz - flow.xz * globals.time * flow_speed;
+    let dx = sin(p.x * 1.7 + time) * 0.6 + sin((p.x + p.y) * 2.9 + time * 1.3) * 0.4;
+    let dz = cos(p.y * 1.9 - time * 0.8) * 0.6 + cos((p.x - p.y) * 3.1 + time * 1.1) * 0.4;
+
+    // Sides only ripple a little, they're mostly falling
+    let strength = select(wave_strength * 0.3, wave_strength, abs(normal.y) > 0.5);
+    return normalize(normal + vec3(dx, 0.0, dz) * strength);
+}
+
+fn apply_view_fog(color: vec4<f32>, world_position: vec3<f32>) -> vec4<f32> {
+    let distance = length(world_position - view.world_position.xyz);
+    let scattering = vec3(0.0);
+    if fog.mode == FOG_MODE_LINEAR {
+        return linear_fog(fog, color, distance, scattering);
+    } else if fog.mode == FOG_MODE_EXPONENTIAL {
+        return exponential_fog(fog, color, distance, scattering);
+    } else if fog.mode == FOG_MODE_EXPONENTIAL_SQUARED {
+        return exponential_squared_fog(fog, color, distance, scattering);
+    } else if fog.mode == FOG_MODE_ATMOSPHERIC {
+        return atmospheric_fog(fog, color, distance, scattering);
+    }
+    return color;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(in.world_normal);
+    let offset = -face_flow(normal, in.flow) * globals.time * flow_speed;
+    let uv = scroll_in_tile(in.uv, in.tile, offset);
+    // Wrapping around the tile jumps across it, which would throw off the mip level
+    let texel = textureSampleLevel(texture, texture_sampler, uv, 0.0);
+
+    let deep = saturate((in.depth - 1.0) / max(deep_depth - 1.0, 1.0));
+    let tint = mix(shallow_color, deep_color, deep);
+
+    // The vertex colour holds the AO and the sky and block light, like on every other block.
+    // On top of that the waves catch a bit more of the ambient light the flatter they look
+    // from where the camera is
+    let n = wave_normal(normal, in.world_position, in.flow);
+    let v = normalize(view.world_position.xyz - in.world_position);
+    let fresnel = pow(1.0 - saturate(abs(dot(n, v))), 5.0);
+    let ambient = lights.ambient_color.rgb * view.exposure;
+
+    let rgb = texel.rgb * tint.rgb * in.color.rgb * ambient * (1.0 + fresnel * 0.5);
+    var color = vec4(rgb, mix(tint.a, 1.0, fresnel * 0.5) * texel.a);
+
+    color = apply_view_fog(color, in.world_position);
+#ifdef TONEMAP_IN_SHADER
+    color = tone_mapping(color, view.color_grading);
+#endif
+    return color;
+}
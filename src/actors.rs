@@ -1 +1,2 @@
 pub mod debug_camera;
+pub mod voxel_editor;
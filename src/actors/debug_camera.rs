@@ -4,6 +4,7 @@ use bevy::prelude::*;
 use bevy::window::{CursorGrabMode, PrimaryWindow};
 use bevy_atmosphere::plugin::AtmosphereCamera;
 use crate::global::KeyBinds;
+use crate::voxel::streaming::ChunkLoader;
 
 /// A plugin for the Debug Camera.
 /// Fly around freely with no restrictions.
@@ -48,6 +49,7 @@ fn spawn_debug_camera(mut commands: Commands) {
             ..default()
         },
         DebugCamera,
+        ChunkLoader,
         AtmosphereCamera::default(),
          FogSettings {
              color: Color::hex("#c2b797").unwrap(),
@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+use crate::actors::debug_camera::DebugCamera;
+use crate::global::KeyBinds;
+use crate::voxel::liquid::LiquidSimulation;
+use crate::voxel::registry::BlockRegistry;
+use crate::voxel::tasks::ChunkTasks;
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::world::VoxelWorld;
+
+/// Lets you break and place voxels with the Debug Camera.
+pub struct VoxelEditorPlugin;
+
+#[derive(Resource)]
+pub struct VoxelEditor {
+    /// What gets placed.
+    pub selected: VoxelType,
+    /// How far away you can edit voxels.
+    pub reach: f32,
+}
+
+impl Default for VoxelEditor {
+    fn default() -> Self {
+        Self {
+            selected: VoxelType::STONE,
+            reach: 8.0,
+        }
+    }
+}
+
+impl Plugin for VoxelEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelEditor>()
+            .add_systems(Update, (select_voxel, edit_voxels).run_if(resource_exists::<BlockRegistry>));
+    }
+}
+
+/// Cycles through every visible type in the BlockRegistry.
+fn select_voxel(
+    mut editor: ResMut<VoxelEditor>,
+    blocks: Res<BlockRegistry>,
+    key_binds: Res<KeyBinds>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    if keys.just_pressed(key_binds.next_voxel) {
+        let placeable: Vec<VoxelType> = blocks.iter()
+            .filter(|(_, block)| block.is_visible())
+            .map(|(voxel_type, _)| voxel_type)
+            .collect();
+        if placeable.is_empty() {
+            return;
+        }
+
+        let current = placeable.iter()
+            .position(|voxel_type| *voxel_type == editor.selected)
+            .unwrap_or(0);
+        editor.selected = placeable[(current + 1) % placeable.len()];
+    }
+}
+
+/// Breaks the voxel the camera is looking at, or places the selected
+/// type against the face that was hit. Only the affected chunks get remeshed.
+#[allow(clippy::too_many_arguments)]
+fn edit_voxels(
+    editor: Res<VoxelEditor>,
+    blocks: Res<BlockRegistry>,
+    key_binds: Res<KeyBinds>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<&Transform, With<DebugCamera>>,
+    mut voxel_world: ResMut<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut liquids: ResMut<LiquidSimulation>,
+) {
+    // Only edit while the mouse is captured, otherwise we'd edit when clicking the UI
+    let Ok(window) = primary_window.get_single() else {
+        return;
+    };
+    if window.cursor.grab_mode == CursorGrabMode::None {
+        return;
+    }
+
+    let breaking = mouse.just_pressed(key_binds.break_voxel);
+    let placing = mouse.just_pressed(key_binds.place_voxel);
+    if !breaking && !placing {
+        return;
+    }
+
+    let Ok(transform) = camera_query.get_single() else {
+        return;
+    };
+
+    let Some(hit) = voxel_world.raycast(&blocks, transform.translation, *transform.forward(), editor.reach, true) else {
+        return;
+    };
+
+    let (world_pos, voxel_type) = if breaking {
+        (hit.position, VoxelType::AIR)
+    } else {
+        // Can't place against a voxel we're standing inside of
+        if hit.normal == IVec3::ZERO {
+            return;
+        }
+
+        let place_pos = hit.position + hit.normal;
+        match voxel_world.get_voxel(place_pos).map(|voxel_type| blocks.get(voxel_type)) {
+            Some(block) if !block.is_visible() || block.is_liquid() => (place_pos, editor.selected),
+            _ => return,
+        }
+    };
+
+    let dirty_chunks = voxel_world.set_voxel(&blocks, world_pos, voxel_type);
+    liquids.voxel_changed(world_pos);
+
+    for chunk_pos in dirty_chunks {
+        chunk_tasks.queue_remesh(chunk_pos);
+    }
+}
@@ -0,0 +1,201 @@
+//! Samples the world generator over a rectangle of columns, without a window or GPU,
+//! and writes what it found to PNGs. Prints the height stats, so runs with different
+//! parameters can be compared quickly.
+//!
+//! Takes the same `--preset`, `--worldgen`, `--seed`, `--generator` and `--heightmap`
+//! arguments as the game, see `WorldGenConfig::from_args`, plus:
+//!
+//! * `--origin <x>,<z>`: World column at the center of the images. Defaults to 0,0.
+//! * `--size <width>x<height>`: Size of the images in pixels. Defaults to 512x512.
+//! * `--step <voxels>`: Voxels between two pixels. Defaults to 4.
+//! * `--height-range <min>,<max>`: Heights mapped to black and white in the heightmap,
+//!   so images of different runs can be compared. Defaults to -256,256.
+//! * `--out <directory>`: Where the images go. Defaults to `worldgen`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use bevy::prelude::World;
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use aravoxel_bevy2::worldgen::biome::BiomeRegistry;
+use aravoxel_bevy2::worldgen::config::WorldGenConfig;
+use aravoxel_bevy2::worldgen::generator::ChunkGenerators;
+use aravoxel_bevy2::worldgen::ores::OreRegistry;
+
+/// Colours for the biome map, picked by biome id.
+const BIOME_COLOURS: [[u8; 3]; 8] = [
+    [121, 192, 90],
+    [34, 110, 52],
+    [230, 206, 128],
+    [240, 244, 250],
+    [160, 82, 45],
+    [70, 130, 180],
+    [186, 85, 211],
+    [128, 128, 128],
+];
+
+struct Options {
+    origin: (i32, i32),
+    size: (u32, u32),
+    step: i32,
+    height_range: (f64, f64),
+    out: PathBuf,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            origin: (0, 0),
+            size: (512, 512),
+            step: 4,
+            height_range: (-256.0, 256.0),
+            out: PathBuf::from("worldgen"),
+        }
+    }
+}
+
+/// Parses "<a><separator><b>", like "3,4".
+fn parse_pair<T: std::str::FromStr>(value: &str, separator: char) -> Option<(T, T)> {
+    let (a, b) = value.split_once(separator)?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.as_str();
+        let value = args.next().ok_or_else(|| format!("{flag} needs a value"))?;
+        let invalid = || format!("\"{value}\" is not a valid value for {flag}");
+
+        match flag {
+            // Those are WorldGenConfig's
+            "--preset" | "--worldgen" | "--seed" | "--generator" | "--heightmap" => {}
+            "--origin" => options.origin = parse_pair(value, ',').ok_or_else(invalid)?,
+            "--size" => options.size = parse_pair(value, 'x').ok_or_else(invalid)?,
+            "--step" => options.step = value.parse().map_err(|_| invalid())?,
+            "--height-range" => options.height_range = parse_pair(value, ',').ok_or_else(invalid)?,
+            "--out" => options.out = PathBuf::from(value),
+            _ => return Err(format!("unknown argument {flag}")),
+        }
+    }
+
+    if options.size.0 == 0 || options.size.1 == 0 || options.step <= 0 {
+        return Err("--size and --step have to be bigger than zero".into());
+    }
+
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = parse_options(&args)?;
+    let config = WorldGenConfig::from_args(args).map_err(|err| err.to_string())?;
+
+    // The generators get built out of the same resources as in the game
+    let mut world = World::new();
+    world.insert_resource(config.clone());
+    world.insert_resource(BiomeRegistry::default());
+    world.insert_resource(OreRegistry::default());
+    let generators = ChunkGenerators::default();
+    let generator = generators.build(&config.generator, &world)
+        .ok_or_else(|| format!("there is no generator called \"{}\"", config.generator))?;
+
+    let (width, height) = options.size;
+    let left = options.origin.0 - width as i32 * options.step / 2;
+    let top = options.origin.1 - height as i32 * options.step / 2;
+    println!("seed {}, preset {}, generator {}", config.seed, config.preset, config.generator);
+    println!(
+        "{width}x{height} columns from ({left}, {top}) to ({}, {}), every {} voxels",
+        left + (width as i32 - 1) * options.step,
+        top + (height as i32 - 1) * options.step,
+        options.step,
+    );
+
+    let biome_count = generator.biomes().map_or(0, |biomes| biomes.iter().count());
+    let mut heightmap = GrayImage::new(width, height);
+    let mut water = GrayImage::new(width, height);
+    let mut biome_map = RgbImage::new(width, height);
+    let mut has_biomes = false;
+
+    let mut min_height = f64::MAX;
+    let mut max_height = f64::MIN;
+    let mut height_sum = 0.0;
+    let mut water_columns = 0u64;
+    let mut biome_columns = vec![0u64; biome_count];
+
+    for y in 0..height {
+        for x in 0..width {
+            let wx = (left + x as i32 * options.step) as f64;
+            let wz = (top + y as i32 * options.step) as f64;
+            let sample = generator.sample_column(wx, wz)
+                .ok_or_else(|| format!("the {} generator can't be sampled", config.generator))?;
+
+            min_height = min_height.min(sample.height);
+            max_height = max_height.max(sample.height);
+            height_sum += sample.height;
+
+            let (low, high) = options.height_range;
+            let brightness = ((sample.height - low) / (high - low)).clamp(0.0, 1.0);
+            heightmap.put_pixel(x, y, Luma([(brightness * 255.0).round() as u8]));
+
+            if sample.water_level.is_some_and(|water_level| sample.height < water_level) {
+                water.put_pixel(x, y, Luma([255]));
+                water_columns += 1;
+            }
+
+            if let Some(biome) = sample.biome {
+                has_biomes = true;
+                biome_map.put_pixel(x, y, Rgb(BIOME_COLOURS[biome.0 % BIOME_COLOURS.len()]));
+                if let Some(count) = biome_columns.get_mut(biome.0) {
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    let columns = width as f64 * height as f64;
+    println!(
+        "height: min {min_height:.1}, max {max_height:.1}, mean {:.1}",
+        height_sum / columns,
+    );
+    println!("water: {:.1}%", water_columns as f64 / columns * 100.0);
+    if let Some(biomes) = generator.biomes() {
+        for (id, biome) in biomes.iter() {
+            let [r, g, b] = BIOME_COLOURS[id.0 % BIOME_COLOURS.len()];
+            println!(
+                "biome {}: {:.1}% (#{r:02x}{g:02x}{b:02x})",
+                biome.name,
+                biome_columns[id.0] as f64 / columns * 100.0,
+            );
+        }
+    }
+
+    fs::create_dir_all(&options.out)
+        .map_err(|err| format!("could not create {}: {err}", options.out.display()))?;
+
+    let mut images = vec![("heightmap.png", heightmap.save(options.out.join("heightmap.png")))];
+    images.push(("water.png", water.save(options.out.join("water.png"))));
+    if has_biomes {
+        images.push(("biomes.png", biome_map.save(options.out.join("biomes.png"))));
+    }
+
+    for (name, result) in images {
+        let path = options.out.join(name);
+        result.map_err(|err| format!("could not write {}: {err}", path.display()))?;
+        println!("wrote {}", path.display());
+    }
+
+    Ok(())
+}
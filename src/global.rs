@@ -11,6 +11,9 @@ pub struct KeyBinds {
     pub move_descend: KeyCode,
     pub sprint: KeyCode,
     pub toggle_cursor: KeyCode,
+    pub break_voxel: MouseButton,
+    pub place_voxel: MouseButton,
+    pub next_voxel: KeyCode,
 }
 
 impl Default for KeyBinds {
@@ -24,19 +27,36 @@ impl Default for KeyBinds {
             move_descend: KeyCode::ControlLeft,
             sprint: KeyCode::ShiftLeft,
             toggle_cursor: KeyCode::Escape,
+            break_voxel: MouseButton::Left,
+            place_voxel: MouseButton::Right,
+            next_voxel: KeyCode::KeyE,
         }
     }
 }
 
-#[derive(Resource)]
+/// Which algorithm is used to turn a Chunk into a mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshingMode {
+    /// One quad per exposed voxel face.
+    Naive,
+    /// Merges coplanar faces with the same VoxelType and AO into bigger quads.
+    Greedy,
+}
+
+#[derive(Resource, Clone)]
 pub struct Settings {
     pub clown_vomit: bool,
+    pub meshing_mode: MeshingMode,
+    /// Draws liquids with the animated water shader instead of a plain see-through material.
+    pub water_shader: bool,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             clown_vomit: false,
+            meshing_mode: MeshingMode::Greedy,
+            water_shader: true,
         }
     }
 }
@@ -0,0 +1,5 @@
+pub mod actors;
+pub mod global;
+pub mod voxel;
+pub mod ui;
+pub mod worldgen;
@@ -1,16 +1,11 @@
 use bevy::prelude::*;
 use bevy::window::PresentMode;
 use bevy_atmosphere::plugin::AtmospherePlugin;
-use crate::actors::debug_camera::DebugCameraPlugin;
-use crate::global::{GlobalPlugin, KeyBinds, Settings};
-use crate::ui::debug::DebugUIPlugin;
-use crate::voxel::plugin::VoxelWorldPlugin;
-
-mod actors;
-mod global;
-mod voxel;
-mod ui;
-mod worldgen;
+use aravoxel_bevy2::actors::debug_camera::DebugCameraPlugin;
+use aravoxel_bevy2::actors::voxel_editor::VoxelEditorPlugin;
+use aravoxel_bevy2::global::{GlobalPlugin, KeyBinds, Settings};
+use aravoxel_bevy2::ui::debug::DebugUIPlugin;
+use aravoxel_bevy2::voxel::plugin::VoxelWorldPlugin;
 
 fn main() {
     App::new()
@@ -39,6 +34,7 @@ fn main() {
         .add_plugins((
             GlobalPlugin,
             DebugCameraPlugin,
+            VoxelEditorPlugin,
             VoxelWorldPlugin,
             DebugUIPlugin,
             AtmospherePlugin
@@ -2,6 +2,13 @@ use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use crate::actors::debug_camera::DebugCamera;
+use crate::actors::voxel_editor::VoxelEditor;
+use crate::global::{MeshingMode, Settings};
+use crate::voxel::liquid::LiquidSimulation;
+use crate::voxel::registry::BlockRegistry;
+use crate::worldgen::config::WorldGenConfig;
+use crate::worldgen::ores::{OreRegistry, OreStats};
+use crate::worldgen::world::VoxelWorld;
 
 /// Shows a bunch of debug information.
 pub struct DebugUIPlugin;
@@ -13,10 +20,20 @@ impl Plugin for DebugUIPlugin {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn debug_ui(
     mut contexts: EguiContexts,
     diagnostics: Res<DiagnosticsStore>,
     mut camera_query: Query<&mut Transform, With<DebugCamera>>,
+    voxel_world: Res<VoxelWorld>,
+    editor: Res<VoxelEditor>,
+    // Missing when the block definitions didn't load, which is when this is needed most
+    blocks: Option<Res<BlockRegistry>>,
+    liquids: Res<LiquidSimulation>,
+    ores: Res<OreRegistry>,
+    worldgen_config: Res<WorldGenConfig>,
+    mut settings: ResMut<Settings>,
+    mut ore_stats: Local<Option<OreStats>>,
 ) {
     let mut camera_pos: Transform = Transform::default();
 
@@ -25,6 +42,10 @@ fn debug_ui(
         camera_pos = transform.clone();
     }
 
+    // Whatever voxel the camera is looking at
+    let target = blocks.as_ref()
+        .and_then(|blocks| voxel_world.raycast(blocks, camera_pos.translation, *camera_pos.forward(), 64.0, true));
+
     let mut fps = 0.0;
     if let Some(fps_diagnostic) = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS) {
         if let Some(fps_smooth) = fps_diagnostic.smoothed() {
@@ -48,8 +69,22 @@ fn debug_ui(
                     ui.label(format!("{}", f32::floor(camera_pos.translation.z)));
                 });
             });
+            if let Some(blocks) = blocks.as_ref() {
+                ui.horizontal(|ui| {
+                    ui.label("Selected Voxel:");
+                    ui.label(&blocks.get(editor.selected).name);
+                });
+            }
         });
         ui.heading("Voxel World");
+        ui.horizontal(|ui| {
+            ui.label("Seed:");
+            ui.label(format!("{} ({}, {})", worldgen_config.seed, worldgen_config.preset, worldgen_config.generator));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Pending Liquid Updates:");
+            ui.label(format!("{}", liquids.pending()));
+        });
         ui.collapsing("Current Chunk", |ui| {
             ui.horizontal(|ui| {
                 ui.label("Visible Voxels:");
@@ -70,6 +105,54 @@ fn debug_ui(
                 });
             });
         });
+        ui.collapsing("Looking At", |ui| {
+            match target.zip(blocks.as_ref()) {
+                Some((hit, blocks)) => {
+                    ui.horizontal(|ui| {
+                        ui.label("Voxel:");
+                        ui.label(&blocks.get(hit.voxel_type).name);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Position:");
+                        ui.label(format!("{} {} {}", hit.position.x, hit.position.y, hit.position.z));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Distance:");
+                        ui.label(format!("{:.1}", hit.distance));
+                    });
+                }
+                None => {
+                    ui.label("Nothing");
+                }
+            }
+        });
+        ui.collapsing("Ores", |ui| {
+            // Goes through every voxel, so only on request
+            if ui.button("Count loaded chunks").clicked() {
+                let stats = OreStats::collect(&ores, voxel_world.chunks.values().map(|chunk| chunk.as_ref()));
+                info!("Ore stats:\n{stats}");
+                *ore_stats = Some(stats);
+            }
+
+            if let Some(stats) = ore_stats.as_ref() {
+                ui.label(stats.to_string());
+            }
+        });
+        ui.heading("Settings");
+        // Only touch the settings when something changed, everything watching them would redo its work otherwise
+        let mut water_shader = settings.water_shader;
+        if ui.checkbox(&mut water_shader, "Water shader").changed() {
+            settings.water_shader = water_shader;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Meshing:");
+            let mut meshing_mode = settings.meshing_mode;
+            let naive = ui.radio_value(&mut meshing_mode, MeshingMode::Naive, "Naive");
+            let greedy = ui.radio_value(&mut meshing_mode, MeshingMode::Greedy, "Greedy");
+            if naive.changed() || greedy.changed() {
+                settings.meshing_mode = meshing_mode;
+            }
+        });
         ui.heading("Other");
         ui.horizontal(|ui| {
             ui.label("FPS: ");
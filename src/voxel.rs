@@ -1,6 +1,16 @@
 pub mod plugin;
 pub mod util;
 pub mod chunk;
+pub mod raycast;
+pub mod light;
+pub mod liquid;
+pub mod registry;
+pub mod atlas;
+pub mod block_material;
 pub(crate) mod voxel;
 mod chunk_mesh;
-mod mesh;
\ No newline at end of file
+mod mesh;
+mod storage;
+pub mod tasks;
+pub mod water;
+pub mod streaming;
\ No newline at end of file
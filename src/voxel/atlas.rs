@@ -0,0 +1,102 @@
+use bevy::math::IVec3;
+use serde::Deserialize;
+
+/// Describes how voxel_atlas.png is cut up into tiles.
+///
+/// Tiles sit in a grid of `columns` x `rows`. Every tile is `tile_size`
+/// texels wide, with `padding` texels on each side of it. The padding
+/// should repeat the edge of the tile, so mip levels and linear filtering
+/// blend with the same colour instead of the tile next door.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct AtlasLayout {
+    pub columns: u32,
+    pub rows: u32,
+    /// Size of a tile in texels, without its padding.
+    pub tile_size: u32,
+    #[serde(default)]
+    pub padding: u32,
+    /// How far in texels the UVs are pulled in from the edge of a tile.
+    /// Half a texel keeps the sampler from reaching outside of it.
+    #[serde(default = "AtlasLayout::default_inset")]
+    pub inset: f32,
+}
+
+impl Default for AtlasLayout {
+    fn default() -> Self {
+        Self {
+            columns: 1,
+            rows: 1,
+            tile_size: 1,
+            padding: 0,
+            inset: Self::default_inset(),
+        }
+    }
+}
+
+impl AtlasLayout {
+    fn default_inset() -> f32 {
+        0.5
+    }
+
+    /// Whether a tile, given as (column, row), is inside the atlas.
+    pub fn contains(&self, tile: (u32, u32)) -> bool {
+        tile.0 < self.columns && tile.1 < self.rows
+    }
+
+    /// The area of the atlas a tile covers, in UV space.
+    pub fn uv_rect(&self, tile: (u32, u32)) -> UvRect {
+        let cell_size = (self.tile_size + self.padding * 2) as f32;
+        let width = cell_size * self.columns as f32;
+        let height = cell_size * self.rows as f32;
+
+        // Never inset past the middle of the tile
+        let inset = self.inset.min(self.tile_size as f32 / 2.0);
+        let x = tile.0 as f32 * cell_size + self.padding as f32;
+        let y = tile.1 as f32 * cell_size + self.padding as f32;
+
+        UvRect {
+            min: [(x + inset) / width, (y + inset) / height],
+            max: [(x + self.tile_size as f32 - inset) / width, (y + self.tile_size as f32 - inset) / height],
+        }
+    }
+}
+
+/// A rectangle in UV space, `min` being the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    /// How big the rectangle is, in UV space. Every tile of an atlas is the same size.
+    pub fn size(&self) -> [f32; 2] {
+        [self.max[0] - self.min[0], self.max[1] - self.min[1]]
+    }
+
+    /// The UVs for the four corners of a face pointing towards `normal`, which is `size` voxels
+    /// big along its first and second corner axis. Corners are in the order the meshers
+    /// emit them, see `GreedyFace`. Side faces are kept upright, so the top of the tile
+    /// is at the top of the face.
+    pub fn face_uvs(&self, normal: IVec3, size: [f32; 2]) -> FaceUvs {
+        let [width, height] = size;
+
+        let uvs = if normal.y != 0 {
+            [[0.0, 0.0], [width, 0.0], [width, height], [0.0, height]]
+        } else {
+            // The first corner axis of a side face is y, which goes against v.
+            [[0.0, width], [0.0, 0.0], [height, 0.0], [height, width]]
+        };
+
+        FaceUvs { uvs, tile: self.min }
+    }
+}
+
+/// Where a face gets its texture from. `uvs` count tiles from the corner of the face,
+/// and `tile` is where the tile starts in the atlas. The block shaders wrap the UVs around
+/// inside the tile, so a merged face repeats its tile instead of stretching one over the whole face.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceUvs {
+    pub uvs: [[f32; 2]; 4],
+    pub tile: [f32; 2],
+}
@@ -0,0 +1,47 @@
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::prelude::*;
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+use crate::voxel::plugin::ChunkMaterials;
+use crate::voxel::registry::BlockRegistry;
+
+/// What chunk meshes are drawn with: a StandardMaterial that finds the tile
+/// of every face in the atlas on its own, see `AtlasTiling`.
+pub type BlockMaterial = ExtendedMaterial<StandardMaterial, AtlasTiling>;
+
+/// Wraps the UVs of every face around inside its tile of the atlas. Faces count their UVs
+/// in tiles and bring where their tile starts along as a second set of UVs, see `FaceUvs`,
+/// so a greedy quad repeats its tile once for every voxel it covers.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct AtlasTiling {
+    /// How big a tile is in UV space.
+    #[uniform(100)]
+    pub tile_size: Vec2,
+}
+
+impl Default for AtlasTiling {
+    fn default() -> Self {
+        Self {
+            tile_size: Vec2::ONE,
+        }
+    }
+}
+
+impl MaterialExtension for AtlasTiling {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/blocks.wgsl".into()
+    }
+}
+
+/// Tells the block materials how big the tiles of the atlas are, once the blocks are known.
+pub fn update_block_atlas(
+    blocks: Res<BlockRegistry>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut block_materials: ResMut<Assets<BlockMaterial>>,
+) {
+    let tile_size = Vec2::from(blocks.atlas.uv_rect((0, 0)).size());
+    for handle in [&chunk_materials.solid, &chunk_materials.liquid, &chunk_materials.cutout] {
+        if let Some(material) = block_materials.get_mut(handle) {
+            material.extension.tile_size = tile_size;
+        }
+    }
+}
@@ -1,25 +1,141 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use bevy::math::{IVec3};
-use crate::voxel::util::{CHUNK_SIZE, CHUNK_VOL, voxel_index};
-use crate::voxel::voxel::{Voxel, VoxelType};
+use crate::voxel::registry::BlockRegistry;
+use crate::voxel::light::{LightChannel, LightLevels, MAX_LIGHT};
+use crate::voxel::liquid::SOURCE_LEVEL;
+use crate::voxel::storage::{NibbleStorage, VoxelStorage};
+use crate::voxel::util::{CHUNK_SIZE, chunk_position, local_position, voxel_index};
+use crate::voxel::voxel::VoxelType;
+
+/// All the loaded chunks, keyed by chunk position.
+/// Chunks are reference counted so background tasks can hold
+/// on to them without copying all the voxels.
+pub type ChunkMap = HashMap<IVec3, Arc<Chunk>>;
 
 #[derive(Clone)]
 pub struct Chunk {
     pub position: IVec3,
-    pub voxels: Vec<Voxel>,
+    voxels: VoxelStorage,
+    /// One for every LightChannel. Not saved, it's worked out again whenever the chunk is loaded.
+    light: [NibbleStorage; 4],
+    /// How far every liquid voxel is from its source, see `voxel::liquid`.
+    liquid_levels: NibbleStorage,
 }
 
 impl Chunk {
+    /// Creates a Chunk filled with air.
     pub fn new(position: IVec3) -> Self {
         Self {
             position,
-            voxels: vec![Voxel::default(); CHUNK_VOL as usize],
+            voxels: VoxelStorage::Single(VoxelType::AIR),
+            light: std::array::from_fn(|_| NibbleStorage::Uniform(0)),
+            liquid_levels: NibbleStorage::Uniform(SOURCE_LEVEL),
+        }
+    }
+
+    /// Creates a Chunk where every voxel is the same type.
+    pub fn filled(position: IVec3, voxel_type: VoxelType) -> Self {
+        Self {
+            position,
+            voxels: VoxelStorage::Single(voxel_type),
+            light: std::array::from_fn(|_| NibbleStorage::Uniform(0)),
+            liquid_levels: NibbleStorage::Uniform(SOURCE_LEVEL),
+        }
+    }
+
+    /// The VoxelType at a local position. The position has to be inside the chunk.
+    pub fn get(&self, local_pos: IVec3) -> VoxelType {
+        self.voxels.get(voxel_index(local_pos.x, local_pos.y, local_pos.z))
+    }
+
+    /// Changes the VoxelType at a local position. The position has to be inside the chunk.
+    /// Liquids placed like this are sources.
+    pub fn set(&mut self, local_pos: IVec3, voxel_type: VoxelType) {
+        let index = voxel_index(local_pos.x, local_pos.y, local_pos.z);
+        self.voxels.set(index, voxel_type);
+        self.liquid_levels.set(index, SOURCE_LEVEL);
+    }
+
+    /// The liquid level at a local position. Only means something for liquid voxels.
+    pub fn liquid_level(&self, local_pos: IVec3) -> u8 {
+        self.liquid_levels.get(voxel_index(local_pos.x, local_pos.y, local_pos.z))
+    }
+
+    pub fn set_liquid_level(&mut self, local_pos: IVec3, level: u8) {
+        self.liquid_levels.set(voxel_index(local_pos.x, local_pos.y, local_pos.z), level);
+    }
+
+    /// The light of a channel at a local position, from 0 to `MAX_LIGHT`.
+    /// The position has to be inside the chunk.
+    pub fn light(&self, channel: LightChannel, local_pos: IVec3) -> u8 {
+        self.light[channel as usize].get(voxel_index(local_pos.x, local_pos.y, local_pos.z))
+    }
+
+    /// Changes the light of a channel at a local position. The position has to be inside the chunk.
+    pub fn set_light(&mut self, channel: LightChannel, local_pos: IVec3, level: u8) {
+        self.light[channel as usize].set(voxel_index(local_pos.x, local_pos.y, local_pos.z), level);
+    }
+
+    /// Makes the whole chunk dark.
+    pub fn clear_light(&mut self) {
+        self.light = std::array::from_fn(|_| NibbleStorage::Uniform(0));
+    }
+
+    /// Every channel of light at a local position that might be in one of the neighbouring chunks.
+    /// Neighbours that aren't loaded count as open sky, like the faces drawn against them.
+    pub fn light_at(&self, local_pos: IVec3, world_chunks: &ChunkMap) -> LightLevels {
+        match self.locate(local_pos, world_chunks) {
+            Some((chunk, local_pos)) => LightChannel::ALL.map(|channel| chunk.light(channel, local_pos)),
+            None => [MAX_LIGHT, 0, 0, 0],
         }
     }
 
+    /// The VoxelType and liquid level at a local position that might be in one of the
+    /// neighbouring chunks, if that chunk is loaded.
+    pub fn liquid_at(&self, local_pos: IVec3, world_chunks: &ChunkMap) -> Option<(VoxelType, u8)> {
+        self.locate(local_pos, world_chunks)
+            .map(|(chunk, local_pos)| (chunk.get(local_pos), chunk.liquid_level(local_pos)))
+    }
+
+    /// The chunk a local position that might be outside of this one is in,
+    /// and the position inside that chunk.
+    fn locate<'a>(&'a self, local_pos: IVec3, world_chunks: &'a ChunkMap) -> Option<(&'a Chunk, IVec3)> {
+        let offset = chunk_position(local_pos);
+        if offset == IVec3::ZERO {
+            return Some((self, local_pos));
+        }
+
+        world_chunks.get(&(self.position + offset)).map(|chunk| (chunk.as_ref(), local_position(local_pos)))
+    }
+
+    /// If the whole chunk is a single VoxelType, returns it.
+    pub fn single_type(&self) -> Option<VoxelType> {
+        match self.voxels {
+            VoxelStorage::Single(voxel_type) => Some(voxel_type),
+            VoxelStorage::Paletted(_) => None,
+        }
+    }
+
+    /// Shrinks the storage down to what the chunk actually uses.
+    /// Should be called once a batch of changes, like generation, is done.
+    pub fn compact(&mut self) {
+        self.voxels.compact();
+        for light in self.light.iter_mut() {
+            light.compact();
+        }
+        self.liquid_levels.compact();
+    }
+
+    /// Turns a local position into a world position.
+    pub fn world_position(&self, local_pos: IVec3) -> IVec3 {
+        self.position * CHUNK_SIZE + local_pos
+    }
+
     /// Determines if a position is occupied by something "solid".
     /// Used to determine which sides of a voxel we render.
     ///
+    /// * `blocks`: The BlockRegistry, to look up how the VoxelTypes behave.
     /// * `current_voxel_type`: Some types, such as liquids, are solid but
     /// should still render blocks next to them under certain conditions.
     /// * `voxel_pos`: The voxel position we want to check.
@@ -27,9 +143,10 @@ impl Chunk {
     /// voxel position is outside its local chunk's bounds.
     pub fn is_void(
         &self,
+        blocks: &BlockRegistry,
         current_voxel_type: &VoxelType,
         voxel_pos: IVec3,
-        world_chunks: &HashMap<IVec3, Chunk>,
+        world_chunks: &ChunkMap,
     ) -> bool {
         let x = voxel_pos.x;
         let y = voxel_pos.y;
@@ -40,17 +157,16 @@ impl Chunk {
             && (0..CHUNK_SIZE).contains(&y)
             && (0..CHUNK_SIZE).contains(&z)
         {
-            // Voxel exists inside our chunk. Get the index and check it.
-            let idx = voxel_index(x, y, z);
-            if let Some(voxel) = self.voxels.get(idx) {
-                // If the current Voxel is liquid, we don't want to render any sides
-                // that are within the body of water itself, so we only check if
-                // the neighbor is air or not.
-                return if current_voxel_type.is_liquid() {
-                    !voxel.voxel_type.is_visible()
-                } else {
-                    voxel.voxel_type.should_render()
-                };
+            // Voxel exists inside our chunk. Check it.
+            let voxel = blocks.get(self.get(voxel_pos));
+
+            // If the current Voxel is liquid, we don't want to render any sides
+            // that are within the body of water itself, so we only check if
+            // the neighbor is air or not.
+            if blocks.get(*current_voxel_type).is_liquid() {
+                !voxel.is_visible()
+            } else {
+                voxel.should_render()
             }
         } else {
             // Voxel exceeds chunk boundaries.
@@ -84,40 +200,34 @@ impl Chunk {
                 neighbor_voxel_pos.z = 31;
             }
 
-            return Chunk::check_neighboring_chunk(current_voxel_type, neighbor_chunk_idx, neighbor_voxel_pos, world_chunks);
+            Chunk::check_neighboring_chunk(blocks, current_voxel_type, neighbor_chunk_idx, neighbor_voxel_pos, world_chunks)
         }
-        true
     }
 
     /// Tries to check the desired Voxel inside a specified Chunk.
     ///
+    /// * `blocks`: The BlockRegistry, to look up how the VoxelTypes behave.
     /// * `current_voxel_type`: VoxelType of the current voxel.
     /// Used so we can differentiate against normal solids and "liquids".
     /// * `chunk_idx`: The key for the Chunk we're interested in.
     /// * `voxel_pos`: The local position of the Voxel we're interested in.
     /// * `world_chunks`: All the loaded chunks located in our world.
     fn check_neighboring_chunk(
+        blocks: &BlockRegistry,
         current_voxel_type: &VoxelType,
         chunk_idx: IVec3,
         voxel_pos: IVec3,
-        world_chunks: &HashMap<IVec3, Chunk>,
+        world_chunks: &ChunkMap,
     ) -> bool {
-        let x = voxel_pos.x;
-        let y = voxel_pos.y;
-        let z = voxel_pos.z;
         match world_chunks.get(&chunk_idx) {
             Some(chunk) => {
-                let voxel_idx = voxel_index(x, y, z);
-
-                if let Some(voxel) = chunk.voxels.get(voxel_idx) {
-                    return if current_voxel_type.is_liquid() {
-                        !voxel.voxel_type.is_visible()
-                    } else {
-                        voxel.voxel_type.should_render()
-                    };
-                }
+                let voxel = blocks.get(chunk.get(voxel_pos));
 
-                false
+                if blocks.get(*current_voxel_type).is_liquid() {
+                    !voxel.is_visible()
+                } else {
+                    voxel.should_render()
+                }
             }
             None => true
         }
@@ -1,11 +1,142 @@
-use std::collections::HashMap;
 use bevy::math::IVec3;
-use bevy::prelude::{Res};
-use crate::global::Settings;
-use crate::voxel::chunk::Chunk;
-use crate::voxel::mesh::Mesh;
-use crate::voxel::util::{CHUNK_SIZE, get_ao, voxel_index};
-use crate::voxel::voxel::{Voxel};
+use crate::global::{MeshingMode, Settings};
+use crate::voxel::chunk::{Chunk, ChunkMap};
+use crate::voxel::light::VertexLight;
+use crate::voxel::liquid::{SOURCE_LEVEL, liquid_height};
+use crate::voxel::mesh::{Mesh, vertex_color};
+use crate::voxel::registry::{BlockDefinition, BlockRegistry, RenderLayer};
+use crate::voxel::util::{CHUNK_AREA, CHUNK_SIZE, get_ao, get_light};
+use crate::voxel::voxel::VoxelType;
+
+/// Describes one of the six face directions for the greedy mesher.
+/// Axes are indices into an IVec3, so 0 = x, 1 = y and 2 = z.
+struct GreedyFace {
+    normal: IVec3,
+    /// The axis the face is pointing along.
+    axis: usize,
+    /// The two axes spanning the face. Corners are emitted as
+    /// (0, 0), (1, 0), (1, 1), (0, 1) in (u, v), which is the same
+    /// order the naive mesher uses, so the AO from `get_ao` lines up.
+    u: usize,
+    v: usize,
+    /// Index order when the quad is flipped for AO and light, and when it isn't.
+    indices: [[u32; 6]; 2],
+}
+
+impl GreedyFace {
+    /// Turns a slice/plane coordinate into a local voxel position.
+    fn position(&self, d: i32, u: i32, v: i32) -> IVec3 {
+        let mut pos = IVec3::ZERO;
+        pos[self.axis] = d;
+        pos[self.u] = u;
+        pos[self.v] = v;
+        pos
+    }
+}
+
+/// What a visible face in the greedy mask looks like: its type, AO and light.
+/// Only faces that look the same get merged.
+type FaceKey = (VoxelType, [u32; 4], [VertexLight; 4]);
+
+/// Whether a quad should be split into triangles along its other diagonal.
+/// The diagonal goes between the two corners that are brightest together, otherwise
+/// the darkness of AO and light gets smeared across the quad unevenly.
+fn flip_quad(aos: &[u32; 4], lights: &[VertexLight; 4]) -> bool {
+    let brightness = |corner: usize| vertex_color(aos[corner], lights[corner]).iter().sum::<f32>();
+    brightness(1) + brightness(3) > brightness(0) + brightness(2)
+}
+
+/// How high the top corners of a voxel are, in the order the top face's vertices go.
+/// Only liquids are ever lower than the top of their voxel. A corner is the average height
+/// of the liquid in the 4 voxels around it, where air pulls it down and solid blocks
+/// are left out, so the surface slopes down the way the liquid flows. Liquid falling
+/// into any of them fills the corner to the top.
+///
+/// Liquid voxels next to each other share two corners and get the same height there,
+/// so the faces between them stay flat and hidden, like `is_void` expects.
+fn top_heights(
+    chunk: &Chunk,
+    local_pos: IVec3,
+    voxel_type: VoxelType,
+    world_chunks: &ChunkMap,
+    blocks: &BlockRegistry,
+) -> [f32; 4] {
+    if !blocks.get(voxel_type).is_liquid() {
+        return [1.0; 4];
+    }
+
+    let same_liquid = |pos: IVec3| chunk.liquid_at(pos, world_chunks).is_some_and(|(other, _)| other == voxel_type);
+
+    [(0, 0), (1, 0), (1, 1), (0, 1)].map(|(dx, dz)| {
+        let mut total = 0.0;
+        let mut count = 0;
+        for (ox, oz) in [(dx - 1, dz - 1), (dx, dz - 1), (dx - 1, dz), (dx, dz)] {
+            let pos = local_pos + IVec3::new(ox, 0, oz);
+            if same_liquid(pos) && same_liquid(pos + IVec3::Y) {
+                return 1.0;
+            }
+
+            match chunk.liquid_at(pos, world_chunks) {
+                Some((other, level)) if other == voxel_type => {
+                    total += liquid_height(level);
+                    count += 1;
+                }
+                Some((other, _)) if !blocks.get(other).is_visible() => count += 1,
+                _ => {}
+            }
+        }
+
+        // The voxel itself always counts
+        total / count as f32
+    })
+}
+
+/// Which way a liquid flows, from the way its surface slopes. Still liquid doesn't flow,
+/// and flowing liquid with more of it right above is falling.
+fn liquid_flow(
+    chunk: &Chunk,
+    local_pos: IVec3,
+    voxel_type: VoxelType,
+    top: [f32; 4],
+    world_chunks: &ChunkMap,
+) -> [f32; 3] {
+    let flowing = chunk.liquid_level(local_pos) != SOURCE_LEVEL;
+    let falling = chunk.liquid_at(local_pos + IVec3::Y, world_chunks).is_some_and(|(above, _)| above == voxel_type);
+    if flowing && falling {
+        return [0.0, -1.0, 0.0];
+    }
+
+    // Downhill, the steeper the faster
+    [(top[0] + top[3]) - (top[1] + top[2]), 0.0, (top[0] + top[1]) - (top[3] + top[2])]
+}
+
+/// Liquid deeper than this looks the same, see `liquid_depth`.
+const MAX_LIQUID_DEPTH: i32 = 16;
+
+/// How many voxels of the same liquid there are straight down from a voxel, counting itself.
+/// Stops counting at `MAX_LIQUID_DEPTH`.
+fn liquid_depth(chunk: &Chunk, local_pos: IVec3, voxel_type: VoxelType, world_chunks: &ChunkMap) -> f32 {
+    (1..MAX_LIQUID_DEPTH)
+        .take_while(|depth| {
+            chunk.liquid_at(local_pos - IVec3::Y * *depth, world_chunks).is_some_and(|(below, _)| below == voxel_type)
+        })
+        .count() as f32 + 1.0
+}
+
+const GREEDY_FACES: [GreedyFace; 6] = [
+    // Above
+    GreedyFace { normal: IVec3::Y, axis: 1, u: 0, v: 2, indices: [[1, 0, 3, 1, 3, 2], [0, 3, 2, 0, 2, 1]] },
+    // Under
+    GreedyFace { normal: IVec3::NEG_Y, axis: 1, u: 0, v: 2, indices: [[1, 3, 0, 1, 2, 3], [0, 2, 3, 0, 1, 2]] },
+    // Right
+    GreedyFace { normal: IVec3::X, axis: 0, u: 1, v: 2, indices: [[3, 0, 1, 3, 1, 2], [0, 1, 2, 0, 2, 3]] },
+    // Left
+    GreedyFace { normal: IVec3::NEG_X, axis: 0, u: 1, v: 2, indices: [[3, 1, 0, 3, 2, 1], [0, 2, 1, 0, 3, 2]] },
+    // Behind
+    GreedyFace { normal: IVec3::NEG_Z, axis: 2, u: 1, v: 0, indices: [[3, 0, 1, 3, 1, 2], [0, 1, 2, 0, 2, 3]] },
+    // In front
+    GreedyFace { normal: IVec3::Z, axis: 2, u: 1, v: 0, indices: [[3, 1, 0, 3, 2, 1], [0, 2, 1, 0, 3, 2]] },
+];
 
 /// The ChunkMesh holds all relevant data for this specific Chunk.
 pub struct ChunkMesh {
@@ -15,6 +146,13 @@ pub struct ChunkMesh {
     /// All liquids. Since liquids are all transparent, they need
     /// to be in their own mesh.
     pub liquid_mesh: Mesh,
+
+    /// Liquids that give off light, like lava. The water shader lights liquids with the
+    /// ambient light and darkens them with depth, so these keep the block material.
+    pub glowing_liquid_mesh: Mesh,
+
+    /// Blocks with holes in them, like leaves. Drawn with alpha masking.
+    pub cutout_mesh: Mesh,
 }
 
 impl Default for ChunkMesh {
@@ -22,6 +160,8 @@ impl Default for ChunkMesh {
         Self {
             mesh: Mesh::default(),
             liquid_mesh: Mesh::default(),
+            glowing_liquid_mesh: Mesh::default(),
+            cutout_mesh: Mesh::default(),
         }
     }
 }
@@ -31,91 +171,251 @@ impl ChunkMesh {
     pub fn build_chunk_mesh(
         &mut self,
         chunk: &Chunk,
-        chunks: &HashMap<IVec3, Chunk>,
-        settings: &Res<Settings>,
+        chunks: &ChunkMap,
+        blocks: &BlockRegistry,
+        settings: &Settings,
+    ) {
+        // Nothing to draw in a chunk of pure air
+        if chunk.single_type() == Some(VoxelType::AIR) {
+            return;
+        }
+
+        match settings.meshing_mode {
+            MeshingMode::Naive => self.build_naive(chunk, chunks, blocks),
+            MeshingMode::Greedy => self.build_greedy(chunk, chunks, blocks),
+        }
+
+        // Add all the AO and light once the generation is done
+        self.mesh.add_light_color(1.0);
+        self.liquid_mesh.add_light_color(0.3);
+        self.glowing_liquid_mesh.add_light_color(1.0);
+        self.cutout_mesh.add_light_color(1.0);
+
+        if settings.clown_vomit {
+            self.mesh.clown_vomit();
+            self.liquid_mesh.clown_vomit();
+            self.glowing_liquid_mesh.clown_vomit();
+            self.cutout_mesh.clown_vomit();
+        }
+    }
+
+    /// Emits one quad for every exposed voxel face.
+    fn build_naive(&mut self, chunk: &Chunk, chunks: &ChunkMap, blocks: &BlockRegistry) {
+        self.build_naive_where(chunk, chunks, blocks, |block| block.is_visible());
+    }
+
+    /// Emits one quad for every exposed face of the voxels whose blocks pass `filter`.
+    fn build_naive_where(
+        &mut self,
+        chunk: &Chunk,
+        chunks: &ChunkMap,
+        blocks: &BlockRegistry,
+        filter: impl Fn(&BlockDefinition) -> bool,
     ) {
         for x in 0..CHUNK_SIZE {
             for y in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
-                    let index = voxel_index(x, y, z);
+                    let local_pos = IVec3::new(x, y, z);
+                    let voxel_type = chunk.get(local_pos);
+                    if !filter(blocks.get(voxel_type)) {
+                        continue;
+                    }
+
+                    self.create_voxel_data(chunk, local_pos, voxel_type, chunks, blocks);
+                }
+            }
+        }
+    }
+
+    /// Merges coplanar faces that share a VoxelType, AO and light values into larger quads.
+    /// Goes through every slice of the chunk for each face direction, builds a mask
+    /// of the visible faces in that slice and then greedily grows rectangles out of it.
+    /// A merged quad repeats its tile once for every voxel it covers, like the faces it
+    /// replaces, see `FaceUvs`. Cutout faces are never merged, so there's a quad
+    /// for every one of their holes to be cut out of, and neither are liquids.
+    fn build_greedy(&mut self, chunk: &Chunk, world_chunks: &ChunkMap, blocks: &BlockRegistry) {
+        // The surface of a liquid slopes, so liquids get a quad for every face
+        self.build_naive_where(chunk, world_chunks, blocks, |block| block.is_liquid());
+
+        let chunk_world_pos = chunk.position * CHUNK_SIZE;
+        let mask_index = |u: i32, v: i32| (u + v * CHUNK_SIZE) as usize;
+
+        for face in GREEDY_FACES.iter() {
+            // get_ao and get_light only care about the plane, not which way the face is pointing.
+            let plane = face.normal.abs();
+            // Faces pointing in a positive direction sit on the far side of the voxel.
+            let offset = face.normal[face.axis].max(0);
+
+            for d in 0..CHUNK_SIZE {
+                let mut mask: Vec<Option<FaceKey>> = vec![None; CHUNK_AREA as usize];
+
+                for v in 0..CHUNK_SIZE {
+                    for u in 0..CHUNK_SIZE {
+                        let local_pos = face.position(d, u, v);
+                        let voxel_type = chunk.get(local_pos);
+                        let block = blocks.get(voxel_type);
+                        if !block.is_visible() || block.is_liquid() {
+                            continue;
+                        }
+
+                        let neighbor_pos = local_pos + face.normal;
+                        if chunk.is_void(blocks, &voxel_type, neighbor_pos, world_chunks) {
+                            let aos = get_ao(chunk, blocks, &voxel_type, neighbor_pos, plane, world_chunks);
+                            let lights = get_light(chunk, blocks, &voxel_type, neighbor_pos, plane, world_chunks);
+                            mask[mask_index(u, v)] = Some((voxel_type, aos, lights));
+                        }
+                    }
+                }
 
-                    if let Some(voxel) = chunk.voxels.get(index) {
-                        if !voxel.voxel_type.is_visible() {
+                for v in 0..CHUNK_SIZE {
+                    let mut u = 0;
+                    while u < CHUNK_SIZE {
+                        let Some(key) = mask[mask_index(u, v)] else {
+                            u += 1;
                             continue;
+                        };
+
+                        let mergeable = blocks.get(key.0).render_layer != RenderLayer::Cutout;
+
+                        // Grow along u as far as the faces match...
+                        let mut width = 1;
+                        while mergeable && u + width < CHUNK_SIZE && mask[mask_index(u + width, v)].as_ref() == Some(&key) {
+                            width += 1;
                         }
 
-                        self.create_voxel_data(chunk, voxel, chunks);
+                        // ...then along v for as long as the whole row matches.
+                        let mut height = 1;
+                        'grow: while mergeable && v + height < CHUNK_SIZE {
+                            for du in 0..width {
+                                if mask[mask_index(u + du, v + height)].as_ref() != Some(&key) {
+                                    break 'grow;
+                                }
+                            }
+                            height += 1;
+                        }
+
+                        for dv in 0..height {
+                            for du in 0..width {
+                                mask[mask_index(u + du, v + dv)] = None;
+                            }
+                        }
+
+                        let vertices = [(0, 0), (width, 0), (width, height), (0, height)]
+                            .map(|(du, dv)| {
+                                (face.position(d + offset, u + du, v + dv) + chunk_world_pos)
+                                    .as_vec3()
+                                    .to_array()
+                            });
+
+                        let size = [width as f32, height as f32];
+                        self.add_greedy_quad(face, vertices, size, blocks, key);
+                        u += width;
                     }
                 }
             }
         }
+    }
+
+    /// The mesh the faces of a block go into, mostly picked by its RenderLayer.
+    fn layer_mesh(&mut self, block: &BlockDefinition) -> &mut Mesh {
+        match block.render_layer {
+            RenderLayer::Liquid if block.emission != [0; 3] => &mut self.glowing_liquid_mesh,
+            RenderLayer::Liquid => &mut self.liquid_mesh,
+            RenderLayer::Cutout => &mut self.cutout_mesh,
+            RenderLayer::Invisible | RenderLayer::Opaque => &mut self.mesh,
+        }
+    }
 
-        // Add all the AO once the generation is done
-        self.mesh.add_ao_color(1.0);
-        self.liquid_mesh.add_ao_color(0.3);
+    /// Pushes a single merged quad, `size` voxels big, into the right mesh.
+    fn add_greedy_quad(
+        &mut self,
+        face: &GreedyFace,
+        vertices: [[f32; 3]; 4],
+        size: [f32; 2],
+        blocks: &BlockRegistry,
+        (voxel_type, aos, lights): FaceKey,
+    ) {
+        let block = blocks.get(voxel_type);
+        let mesh = self.layer_mesh(block);
 
-        if settings.clown_vomit {
-            self.mesh.clown_vomit();
-            self.liquid_mesh.clown_vomit();
+        // Same flipping rule as the naive mesher
+        if flip_quad(&aos, &lights) {
+            mesh.set_indices(face.indices[0].to_vec());
+        } else {
+            mesh.set_indices(face.indices[1].to_vec());
         }
+
+        mesh.set_vertices(vertices.to_vec());
+        mesh.set_normals(face.normal);
+        mesh.aos.extend_from_slice(&aos);
+        mesh.lights.extend_from_slice(&lights);
+        mesh.set_uvs(blocks.face_uvs(voxel_type, face.normal, size));
     }
 
     /// Creates the ModelVertex vector as well as the index vector for our current Voxel.
     ///
     /// * `chunk`: The Chunk this Voxel resides within.
-    /// * `voxel`: The Voxel itself.
+    /// * `local_pos`: The position of the Voxel inside the chunk.
+    /// * `voxel_type`: The VoxelType of the Voxel.
     /// * `start_index`: The current amount of Vertices. Used to set the indices correctly.
     /// * `world_chunks`: All the chunks inside our world. Used so we can access another Chunk's
     /// Voxels while we draw in case the neighboring Voxel isn't local to our current Chunk.
+    /// * `blocks`: The BlockRegistry, for how the Voxel looks and which mesh it goes in.
     fn create_voxel_data(
         &mut self,
         chunk: &Chunk,
-        voxel: &Voxel,
-        world_chunks: &HashMap<IVec3, Chunk>,
+        local_pos: IVec3,
+        voxel_type: VoxelType,
+        world_chunks: &ChunkMap,
+        blocks: &BlockRegistry,
     ) {
-        let lx = voxel.local_position.x;
-        let ly = voxel.local_position.y;
-        let lz = voxel.local_position.z;
+        let IVec3 { x: lx, y: ly, z: lz } = local_pos;
 
         // World position of Voxel
-        let wx = voxel.world_position.x as f32;
-        let wy = voxel.world_position.y as f32;
-        let wz = voxel.world_position.z as f32;
+        let world_pos = chunk.world_position(local_pos);
+        let wx = world_pos.x as f32;
+        let wy = world_pos.y as f32;
+        let wz = world_pos.z as f32;
 
         // Determine if this should go to the liquid mesh or normal one
-        let mesh = if voxel.voxel_type.is_liquid() {
-            &mut self.liquid_mesh
-        } else {
-            &mut self.mesh
-        };
+        let block = blocks.get(voxel_type);
+        let top = top_heights(chunk, local_pos, voxel_type, world_chunks, blocks);
+        // A liquid that doesn't reach the top of its voxel shows its surface under anything but more liquid
+        let sunken = top.iter().any(|height| *height < 1.0) && chunk.liquid_at(IVec3::new(lx, ly + 1, lz), world_chunks)
+            .is_some_and(|(above, _)| !blocks.get(above).is_liquid());
+        let mesh = self.layer_mesh(block);
+        let first_vertex = mesh.vertices.len();
 
 
         // Check if there is a solid voxel above
-        if chunk.is_void(&voxel.voxel_type, IVec3::new(lx, ly + 1, lz), world_chunks) {
-            let aos = get_ao(chunk, &voxel.voxel_type, IVec3::new(lx, ly + 1, lz), IVec3::Y, world_chunks);
+        if sunken || chunk.is_void(blocks, &voxel_type, IVec3::new(lx, ly + 1, lz), world_chunks) {
+            let aos = get_ao(chunk, blocks, &voxel_type, IVec3::new(lx, ly + 1, lz), IVec3::Y, world_chunks);
+            let lights = get_light(chunk, blocks, &voxel_type, IVec3::new(lx, ly + 1, lz), IVec3::Y, world_chunks);
             // Fixing aos by flipping if needed
-            if aos[1] + aos[3] > aos[0] + aos[2] {
+            if flip_quad(&aos, &lights) {
                 mesh.set_indices(vec![1, 0, 3, 1, 3, 2]);
             } else {
                 mesh.set_indices(vec![0, 3, 2, 0, 2, 1]);
             }
 
             mesh.set_vertices(vec![
-                [wx, wy + 1.0, wz],
-                [wx + 1.0, wy + 1.0, wz],
-                [wx + 1.0, wy + 1.0, wz + 1.0],
-                [wx, wy + 1.0, wz + 1.0],
+                [wx, wy + top[0], wz],
+                [wx + 1.0, wy + top[1], wz],
+                [wx + 1.0, wy + top[2], wz + 1.0],
+                [wx, wy + top[3], wz + 1.0],
             ]);
 
             mesh.set_normals(IVec3::Y);
             mesh.aos.extend_from_slice(&aos);
-            mesh.set_uvs(&voxel.voxel_type);
+            mesh.lights.extend_from_slice(&lights);
+            mesh.set_uvs(blocks.face_uvs(voxel_type, IVec3::Y, [1.0, 1.0]));
         }
 
         // Check under...
-        if chunk.is_void(&voxel.voxel_type, IVec3::new(lx, ly - 1, lz), world_chunks) {
-            let aos = get_ao(chunk, &voxel.voxel_type, IVec3::new(lx, ly - 1, lz), IVec3::Y, world_chunks);
-            if aos[1] + aos[3] > aos[0] + aos[2] {
+        if chunk.is_void(blocks, &voxel_type, IVec3::new(lx, ly - 1, lz), world_chunks) {
+            let aos = get_ao(chunk, blocks, &voxel_type, IVec3::new(lx, ly - 1, lz), IVec3::Y, world_chunks);
+            let lights = get_light(chunk, blocks, &voxel_type, IVec3::new(lx, ly - 1, lz), IVec3::Y, world_chunks);
+            if flip_quad(&aos, &lights) {
                 mesh.set_indices(vec![1, 3, 0, 1, 2, 3]);
             } else {
                 mesh.set_indices(vec![0, 2, 3, 0, 1, 2]);
@@ -131,13 +431,15 @@ impl ChunkMesh {
             );
             mesh.set_normals(IVec3::NEG_Y);
             mesh.aos.extend_from_slice(&aos);
-            mesh.set_uvs(&voxel.voxel_type);
+            mesh.lights.extend_from_slice(&lights);
+            mesh.set_uvs(blocks.face_uvs(voxel_type, IVec3::NEG_Y, [1.0, 1.0]));
         }
 
         // Right
-        if chunk.is_void(&voxel.voxel_type, IVec3::new(lx + 1, ly, lz), world_chunks) {
-            let aos = get_ao(chunk, &voxel.voxel_type, IVec3::new(lx + 1, ly, lz), IVec3::X, world_chunks);
-            if aos[1] + aos[3] > aos[0] + aos[2] {
+        if chunk.is_void(blocks, &voxel_type, IVec3::new(lx + 1, ly, lz), world_chunks) {
+            let aos = get_ao(chunk, blocks, &voxel_type, IVec3::new(lx + 1, ly, lz), IVec3::X, world_chunks);
+            let lights = get_light(chunk, blocks, &voxel_type, IVec3::new(lx + 1, ly, lz), IVec3::X, world_chunks);
+            if flip_quad(&aos, &lights) {
                 mesh.set_indices(vec![3, 0, 1, 3, 1, 2]);
             } else {
                 mesh.set_indices(vec![0, 1, 2, 0, 2, 3]);
@@ -146,21 +448,23 @@ impl ChunkMesh {
             mesh.set_vertices(
                 vec!(
                     [wx + 1.0, wy, wz],
-                    [wx + 1.0, wy + 1.0, wz],
-                    [wx + 1.0, wy + 1.0, wz + 1.0],
+                    [wx + 1.0, wy + top[1], wz],
+                    [wx + 1.0, wy + top[2], wz + 1.0],
                     [wx + 1.0, wy , wz + 1.0],
                 )
             );
 
             mesh.set_normals(IVec3::X);
             mesh.aos.extend_from_slice(&aos);
-            mesh.set_uvs(&voxel.voxel_type);
+            mesh.lights.extend_from_slice(&lights);
+            mesh.set_uvs(blocks.face_uvs(voxel_type, IVec3::X, [1.0, 1.0]));
         }
 
         // Left
-        if chunk.is_void(&voxel.voxel_type, IVec3::new(lx - 1, ly, lz), world_chunks) {
-            let aos = get_ao(chunk, &voxel.voxel_type, IVec3::new(lx - 1, ly, lz), IVec3::X, world_chunks);
-            if aos[1] + aos[3] > aos[0] + aos[2] {
+        if chunk.is_void(blocks, &voxel_type, IVec3::new(lx - 1, ly, lz), world_chunks) {
+            let aos = get_ao(chunk, blocks, &voxel_type, IVec3::new(lx - 1, ly, lz), IVec3::X, world_chunks);
+            let lights = get_light(chunk, blocks, &voxel_type, IVec3::new(lx - 1, ly, lz), IVec3::X, world_chunks);
+            if flip_quad(&aos, &lights) {
                 mesh.set_indices(vec![3, 1, 0, 3, 2, 1]);
             } else {
                 mesh.set_indices(vec![0, 2, 1, 0, 3, 2]);
@@ -169,20 +473,22 @@ impl ChunkMesh {
             mesh.set_vertices(
                 vec!(
                     [wx, wy, wz],
-                    [wx, wy + 1.0, wz],
-                    [wx, wy + 1.0, wz + 1.0],
+                    [wx, wy + top[0], wz],
+                    [wx, wy + top[3], wz + 1.0],
                     [wx, wy, wz + 1.0],
                 )
             );
             mesh.set_normals(IVec3::NEG_X);
             mesh.aos.extend_from_slice(&aos);
-            mesh.set_uvs(&voxel.voxel_type);
+            mesh.lights.extend_from_slice(&lights);
+            mesh.set_uvs(blocks.face_uvs(voxel_type, IVec3::NEG_X, [1.0, 1.0]));
         }
 
         // Behind
-        if chunk.is_void(&voxel.voxel_type, IVec3::new(lx, ly, lz - 1), world_chunks) {
-            let aos = get_ao(chunk, &voxel.voxel_type,  IVec3::new(lx, ly, lz - 1), IVec3::Z, world_chunks);
-            if aos[1] + aos[3] > aos[0] + aos[2] {
+        if chunk.is_void(blocks, &voxel_type, IVec3::new(lx, ly, lz - 1), world_chunks) {
+            let aos = get_ao(chunk, blocks, &voxel_type, IVec3::new(lx, ly, lz - 1), IVec3::Z, world_chunks);
+            let lights = get_light(chunk, blocks, &voxel_type, IVec3::new(lx, ly, lz - 1), IVec3::Z, world_chunks);
+            if flip_quad(&aos, &lights) {
                 mesh.set_indices(vec![3, 0, 1, 3, 1, 2]);
             } else {
                 mesh.set_indices(vec![0, 1, 2, 0, 2, 3]);
@@ -191,38 +497,227 @@ impl ChunkMesh {
             mesh.set_vertices(
                 vec!(
                     [wx, wy, wz],
-                    [wx, wy + 1.0, wz],
-                    [wx + 1.0, wy + 1.0, wz],
+                    [wx, wy + top[0], wz],
+                    [wx + 1.0, wy + top[1], wz],
                     [wx + 1.0, wy, wz],
                 )
             );
             mesh.set_normals(IVec3::NEG_Z);
             mesh.aos.extend_from_slice(&aos);
-            mesh.set_uvs(&voxel.voxel_type);
+            mesh.lights.extend_from_slice(&lights);
+            mesh.set_uvs(blocks.face_uvs(voxel_type, IVec3::NEG_Z, [1.0, 1.0]));
         }
 
         // In front
-        if chunk.is_void(&voxel.voxel_type, IVec3::new(lx, ly, lz + 1), world_chunks) {
-            let aos = get_ao(chunk, &voxel.voxel_type, IVec3::new(lx, ly, lz + 1), IVec3::Z, world_chunks);
-            if aos[1] + aos[3] > aos[0] + aos[2] {
+        if chunk.is_void(blocks, &voxel_type, IVec3::new(lx, ly, lz + 1), world_chunks) {
+            let aos = get_ao(chunk, blocks, &voxel_type, IVec3::new(lx, ly, lz + 1), IVec3::Z, world_chunks);
+            let lights = get_light(chunk, blocks, &voxel_type, IVec3::new(lx, ly, lz + 1), IVec3::Z, world_chunks);
+            if flip_quad(&aos, &lights) {
                 mesh.set_indices(vec![3, 1, 0, 3, 2, 1]);
             } else {
                 mesh.set_indices(vec![0, 2, 1, 0, 3, 2]);
             }
 
-            mesh.set_indices(vec![0, 2, 1, 0, 3, 2]);
             mesh.set_vertices(
                 vec!(
                     [wx, wy, wz + 1.0],
-                    [wx, wy + 1.0, wz + 1.0],
-                    [wx + 1.0, wy + 1.0, wz + 1.0],
+                    [wx, wy + top[3], wz + 1.0],
+                    [wx + 1.0, wy + top[2], wz + 1.0],
                     [wx + 1.0, wy, wz + 1.0],
                 )
             );
 
             mesh.set_normals(IVec3::Z);
             mesh.aos.extend_from_slice(&aos);
-            mesh.set_uvs(&voxel.voxel_type);
+            mesh.lights.extend_from_slice(&lights);
+            mesh.set_uvs(blocks.face_uvs(voxel_type, IVec3::Z, [1.0, 1.0]));
+        }
+
+        // The water shader needs to know how the liquid moves, and how deep it is
+        let added = mesh.vertices.len() - first_vertex;
+        if block.is_liquid() && added > 0 {
+            let flow = liquid_flow(chunk, local_pos, voxel_type, top, world_chunks);
+            let depth = liquid_depth(chunk, local_pos, voxel_type, world_chunks);
+            mesh.flows.extend(std::iter::repeat_n(flow, added));
+            mesh.depths.extend(std::iter::repeat_n(depth, added));
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+    use bevy::math::{IVec3, Vec2, Vec3};
+    use crate::voxel::chunk::{Chunk, ChunkMap};
+    use crate::voxel::mesh::Mesh;
+    use crate::voxel::registry::{BlockDefinitions, BlockRegistry, BlockTextures};
+    use crate::voxel::util::CHUNK_SIZE;
+    use crate::voxel::voxel::VoxelType;
+    use super::ChunkMesh;
+
+    /// The real blocks, except every block gets a tile of its own,
+    /// so the tile of a face tells which block it belongs to.
+    fn blocks() -> BlockRegistry {
+        let definitions: BlockDefinitions = ron::de::from_str(include_str!("../../assets/base.blocks.ron")).unwrap();
+        let columns = definitions.atlas.columns;
+        let blocks = definitions.blocks.into_iter()
+            .map(|mut block| {
+                let tile = (block.id as u32 % columns, block.id as u32 / columns);
+                block.textures = BlockTextures { top: tile, bottom: tile, side: tile, ..Default::default() };
+                block
+            })
+            .collect();
+        BlockRegistry::new(definitions.atlas, blocks).unwrap()
+    }
+
+    /// How much area, and UV area, the faces of every block cover in every direction.
+    type Coverage = HashMap<(IVec3, VoxelType), (f32, f32)>;
+
+    fn coverage(chunk_mesh: &ChunkMesh, blocks: &BlockRegistry) -> Coverage {
+        let tiles: HashMap<[u32; 2], VoxelType> = blocks.iter()
+            .map(|(voxel_type, block)| (blocks.atlas.uv_rect(block.textures.top).min.map(f32::to_bits), voxel_type))
+            .collect();
+
+        let mut coverage = Coverage::new();
+        for mesh in [&chunk_mesh.mesh, &chunk_mesh.liquid_mesh, &chunk_mesh.glowing_liquid_mesh, &chunk_mesh.cutout_mesh] {
+            for quad in 0..quad_count(mesh) {
+                let corner = quad * 4;
+                let [p0, p1, _, p3] = [0, 1, 2, 3].map(|i| Vec3::from(mesh.vertices[corner + i]));
+                let [uv0, uv1, _, uv3] = [0, 1, 2, 3].map(|i| Vec2::from(mesh.uvs[corner + i]));
+                let normal = Vec3::from(mesh.normals[corner]).as_ivec3();
+                let voxel_type = tiles[&mesh.tiles[corner].map(f32::to_bits)];
+
+                let entry = coverage.entry((normal, voxel_type)).or_default();
+                entry.0 += (p1 - p0).cross(p3 - p0).length();
+                entry.1 += (uv1 - uv0).perp_dot(uv3 - uv0).abs();
+            }
+        }
+        coverage
+    }
+
+    fn quad_count(mesh: &Mesh) -> usize {
+        mesh.vertices.len() / 4
+    }
+
+    fn total_quads(chunk_mesh: &ChunkMesh) -> usize {
+        quad_count(&chunk_mesh.mesh)
+            + quad_count(&chunk_mesh.liquid_mesh)
+            + quad_count(&chunk_mesh.glowing_liquid_mesh)
+            + quad_count(&chunk_mesh.cutout_mesh)
+    }
+
+    /// Meshes a chunk both ways, checks that both cover exactly the same faces
+    /// and returns how many quads naive and greedy meshing took.
+    fn mesh_both(chunk: &Chunk, world_chunks: &ChunkMap) -> (usize, usize) {
+        let blocks = blocks();
+        let mut naive = ChunkMesh::default();
+        naive.build_naive(chunk, world_chunks, &blocks);
+        let mut greedy = ChunkMesh::default();
+        greedy.build_greedy(chunk, world_chunks, &blocks);
+
+        let naive_coverage = coverage(&naive, &blocks);
+        let greedy_coverage = coverage(&greedy, &blocks);
+        assert_eq!(
+            naive_coverage.keys().collect::<HashSet<_>>(),
+            greedy_coverage.keys().collect(),
+            "naive and greedy have faces of different blocks and directions",
+        );
+        for (key, (area, uv_area)) in naive_coverage.iter() {
+            let (greedy_area, greedy_uv_area) = greedy_coverage[key];
+            assert!((area - greedy_area).abs() < 1e-3, "{key:?} covers {area} naive but {greedy_area} greedy");
+            assert!((uv_area - greedy_uv_area).abs() < 1e-3, "{key:?} repeats its tile {uv_area} times naive but {greedy_uv_area} greedy");
+        }
+
+        (total_quads(&naive), total_quads(&greedy))
+    }
+
+    fn fill(chunk: &mut Chunk, voxel_type: impl Fn(IVec3) -> VoxelType) {
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let local_pos = IVec3::new(x, y, z);
+                    chunk.set(local_pos, voxel_type(local_pos));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solid_chunk() {
+        let chunk = Chunk::filled(IVec3::ZERO, VoxelType::STONE);
+        let (naive, greedy) = mesh_both(&chunk, &ChunkMap::new());
+        assert_eq!(naive, 6 * (CHUNK_SIZE * CHUNK_SIZE) as usize);
+        assert_eq!(greedy, 6);
+    }
+
+    #[test]
+    fn checkerboard() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        fill(&mut chunk, |pos| if (pos.x + pos.y + pos.z) % 2 == 0 { VoxelType::STONE } else { VoxelType::AIR });
+        let (naive, greedy) = mesh_both(&chunk, &ChunkMap::new());
+        // No two faces touch, so there's nothing to merge
+        assert_eq!(naive, greedy);
+    }
+
+    #[test]
+    fn mixed_types() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        fill(&mut chunk, |pos| match pos.y {
+            y if y < 8 => VoxelType::STONE,
+            y if y < 12 && (pos.x / 4 + pos.z / 3) % 3 == 0 => VoxelType::SAND,
+            y if y < 12 => VoxelType::DIRT,
+            12 => VoxelType::GRASS,
+            13 if pos.x % 7 == 0 && pos.z % 5 == 0 => VoxelType::STONE,
+            _ => VoxelType::AIR,
+        });
+        // A pool with some flowing water in it
+        for x in 10..20 {
+            for z in 10..20 {
+                chunk.set(IVec3::new(x, 12, z), VoxelType::WATER);
+                chunk.set_liquid_level(IVec3::new(x, 12, z), ((x + z) % 4) as u8);
+            }
         }
+
+        let (naive, greedy) = mesh_both(&chunk, &ChunkMap::new());
+        assert!(greedy < naive, "greedy took {greedy} quads, naive {naive}");
+    }
+
+    #[test]
+    fn cutouts() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        fill(&mut chunk, |pos| match pos.y {
+            y if y < 4 => VoxelType::DIRT,
+            y if y < 8 && pos.x % 3 != 0 => VoxelType::LEAVES,
+            8 if pos.z % 2 == 0 => VoxelType::TALL_GRASS,
+            _ => VoxelType::AIR,
+        });
+
+        let blocks = blocks();
+        let mut naive = ChunkMesh::default();
+        naive.build_naive(&chunk, &ChunkMap::new(), &blocks);
+        let mut greedy = ChunkMesh::default();
+        greedy.build_greedy(&chunk, &ChunkMap::new(), &blocks);
+        // Cutouts never merge
+        assert_eq!(quad_count(&naive.cutout_mesh), quad_count(&greedy.cutout_mesh));
+
+        let (naive, greedy) = mesh_both(&chunk, &ChunkMap::new());
+        assert!(greedy < naive, "greedy took {greedy} quads, naive {naive}");
+    }
+
+    #[test]
+    fn chunk_borders() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        fill(&mut chunk, |pos| if pos.y < 20 || pos.x > 25 { VoxelType::STONE } else { VoxelType::AIR });
+
+        // Solid next door hides the faces against it, half a chunk of it hides half of them
+        let mut world_chunks = ChunkMap::new();
+        world_chunks.insert(IVec3::X, Arc::new(Chunk::filled(IVec3::X, VoxelType::STONE)));
+        world_chunks.insert(IVec3::NEG_Y, Arc::new(Chunk::filled(IVec3::NEG_Y, VoxelType::DIRT)));
+        let mut half = Chunk::new(IVec3::Z);
+        fill(&mut half, |pos| if pos.y < 16 { VoxelType::STONE } else { VoxelType::AIR });
+        world_chunks.insert(IVec3::Z, Arc::new(half));
+
+        let (naive, greedy) = mesh_both(&chunk, &world_chunks);
+        assert!(greedy < naive, "greedy took {greedy} quads, naive {naive}");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,573 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use bevy::math::IVec3;
+use crate::voxel::chunk::{Chunk, ChunkMap};
+use crate::voxel::registry::{BlockDefinition, BlockRegistry};
+use crate::voxel::util::{CHUNK_SIZE, chunk_position, chunks_touching, local_position};
+use crate::worldgen::generator::ChunkGenerator;
+use crate::worldgen::world::ColumnMap;
+
+/// The brightest a voxel can be. Open sky is this bright.
+pub const MAX_LIGHT: u8 = 15;
+
+const DIRECTIONS: [IVec3; 6] = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+
+/// The kinds of light every voxel has a level of. Sky light comes down from above,
+/// block light comes from emissive blocks, one channel for each colour.
+/// Every channel spreads on its own, so coloured lights mix where they meet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LightChannel {
+    Sky,
+    Red,
+    Green,
+    Blue,
+}
+
+/// The level of every LightChannel of a voxel, in the order of `LightChannel::ALL`.
+pub type LightLevels = [u8; 4];
+
+/// The light at a corner of a mesh face, every channel averaged over the voxels
+/// around the corner, see `get_light`.
+pub type VertexLight = [f32; 4];
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 4] = [LightChannel::Sky, LightChannel::Red, LightChannel::Green, LightChannel::Blue];
+
+    /// How much of the channel a block gives off by itself.
+    fn emission(self, block: &BlockDefinition) -> u8 {
+        match self {
+            LightChannel::Sky => 0,
+            LightChannel::Red => block.emission[0],
+            LightChannel::Green => block.emission[1],
+            LightChannel::Blue => block.emission[2],
+        }
+    }
+
+    /// The light a block gets from a neighbour with `level` in the direction of `direction`.
+    /// Light fades by one every voxel, except for full sky light going straight down
+    /// through clear blocks, which is how sunlight reaches the bottom of a deep shaft.
+    fn spread(self, level: u8, direction: IVec3, block: &BlockDefinition) -> u8 {
+        if block.opaque {
+            return 0;
+        }
+
+        if self == LightChannel::Sky && level == MAX_LIGHT && direction == IVec3::NEG_Y && block.light_absorption == 0 {
+            return MAX_LIGHT;
+        }
+
+        level.saturating_sub(1 + block.light_absorption)
+    }
+}
+
+/// Guesses whether the sky reaches the top of a column of a chunk, for when the chunk
+/// above it isn't lit yet. Columns above the ground the generator would put there are
+/// open, generators without any ground are open everywhere. `LightUpdate::chunk_added`
+/// fixes up wrong guesses once the chunk above shows up.
+pub fn open_to_sky(
+    generator: &dyn ChunkGenerator,
+    columns: Option<&ColumnMap>,
+    chunk_pos: IVec3,
+    x: i32,
+    z: i32,
+) -> bool {
+    let height = match columns {
+        Some(columns) => Some(columns.get(x, z).height),
+        None => {
+            let world_pos = chunk_pos * CHUNK_SIZE;
+            generator.sample_column((world_pos.x + x) as f64, (world_pos.z + z) as f64).map(|sample| sample.height)
+        }
+    };
+
+    let above = ((chunk_pos.y + 1) * CHUNK_SIZE) as f64;
+    height.is_none_or(|height| height < above)
+}
+
+/// Works out the light of a chunk on its own, as if everything around it was dark,
+/// apart from the sky coming in from above where `open_to_sky` says so.
+/// Doesn't need any other chunks, so it can run inside a task.
+pub fn light_chunk(chunk: &mut Chunk, blocks: &BlockRegistry, open_to_sky: impl Fn(i32, i32) -> bool) {
+    chunk.clear_light();
+
+    // Sunlight falls straight down first...
+    let mut queue = VecDeque::new();
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            if !open_to_sky(x, z) {
+                continue;
+            }
+
+            let mut level = MAX_LIGHT;
+            for y in (0..CHUNK_SIZE).rev() {
+                let local_pos = IVec3::new(x, y, z);
+                level = LightChannel::Sky.spread(level, IVec3::NEG_Y, blocks.get(chunk.get(local_pos)));
+                if level == 0 {
+                    break;
+                }
+
+                chunk.set_light(LightChannel::Sky, local_pos, level);
+                queue.push_back(local_pos);
+            }
+        }
+    }
+
+    // ...then spreads out sideways, and down under overhangs
+    flood_chunk(chunk, blocks, LightChannel::Sky, queue);
+
+    // Block light starts out at the blocks that give it off
+    let emits = |voxel_type| blocks.get(voxel_type).emission != [0; 3];
+    if chunk.single_type().is_none_or(emits) {
+        let mut emitters = Vec::new();
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let local_pos = IVec3::new(x, y, z);
+                    if emits(chunk.get(local_pos)) {
+                        emitters.push(local_pos);
+                    }
+                }
+            }
+        }
+
+        for channel in [LightChannel::Red, LightChannel::Green, LightChannel::Blue] {
+            let mut queue = VecDeque::new();
+            for local_pos in emitters.iter() {
+                let level = channel.emission(blocks.get(chunk.get(*local_pos)));
+                if level > 0 {
+                    chunk.set_light(channel, *local_pos, level);
+                    queue.push_back(*local_pos);
+                }
+            }
+
+            flood_chunk(chunk, blocks, channel, queue);
+        }
+    }
+
+    chunk.compact();
+}
+
+/// Spreads a channel out from the queued voxels, without leaving the chunk.
+fn flood_chunk(chunk: &mut Chunk, blocks: &BlockRegistry, channel: LightChannel, mut queue: VecDeque<IVec3>) {
+    let inside = 0..CHUNK_SIZE;
+    while let Some(local_pos) = queue.pop_front() {
+        let level = chunk.light(channel, local_pos);
+        for direction in DIRECTIONS {
+            let next = local_pos + direction;
+            if !inside.contains(&next.x) || !inside.contains(&next.y) || !inside.contains(&next.z) {
+                continue;
+            }
+
+            let next_level = channel.spread(level, direction, blocks.get(chunk.get(next)));
+            if next_level > chunk.light(channel, next) {
+                chunk.set_light(channel, next, next_level);
+                queue.push_back(next);
+            }
+        }
+    }
+}
+
+/// Runs the same update for every LightChannel.
+/// Returns the loaded chunks whose meshes need to be rebuilt for the new light.
+pub fn update_light(
+    chunks: &mut ChunkMap,
+    blocks: &BlockRegistry,
+    queue: impl Fn(&mut LightUpdate),
+) -> HashSet<IVec3> {
+    let mut dirty = HashSet::new();
+    for channel in LightChannel::ALL {
+        let mut update = LightUpdate::new(chunks, blocks, channel);
+        queue(&mut update);
+        dirty.extend(update.run());
+    }
+    dirty
+}
+
+/// Moves a channel of light through the loaded chunks after some of them,
+/// or some of their voxels, changed. Light doesn't go into chunks
+/// that aren't loaded, and doesn't come out of them either.
+///
+/// Darkness goes first: voxels that lost the light they had are cleared,
+/// along with everything that got its light from them, in a reverse flood fill.
+/// Then light spreads back in from whatever is still lit around them,
+/// and from the emissive blocks the darkness ran into.
+pub struct LightUpdate<'a> {
+    chunks: &'a mut ChunkMap,
+    blocks: &'a BlockRegistry,
+    channel: LightChannel,
+    /// Voxels that went dark, and the light they had.
+    darkened: VecDeque<(IVec3, u8)>,
+    /// Voxels to spread light out from.
+    lit: VecDeque<IVec3>,
+    /// Chunks whose meshes are out of date.
+    dirty: HashSet<IVec3>,
+}
+
+impl<'a> LightUpdate<'a> {
+    pub fn new(chunks: &'a mut ChunkMap, blocks: &'a BlockRegistry, channel: LightChannel) -> Self {
+        Self {
+            chunks,
+            blocks,
+            channel,
+            darkened: VecDeque::new(),
+            lit: VecDeque::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    fn light(&self, world_pos: IVec3) -> Option<u8> {
+        self.chunks.get(&chunk_position(world_pos)).map(|chunk| chunk.light(self.channel, local_position(world_pos)))
+    }
+
+    fn block(&self, world_pos: IVec3) -> Option<&'a BlockDefinition> {
+        let blocks = self.blocks;
+        self.chunks.get(&chunk_position(world_pos)).map(|chunk| blocks.get(chunk.get(local_position(world_pos))))
+    }
+
+    fn set_light(&mut self, world_pos: IVec3, level: u8) {
+        let Some(chunk) = self.chunks.get_mut(&chunk_position(world_pos)) else {
+            return;
+        };
+
+        // Tasks might still be holding on to the chunk, in which case it gets copied
+        Arc::make_mut(chunk).set_light(self.channel, local_position(world_pos), level);
+        self.dirty.extend(chunks_touching(world_pos));
+    }
+
+    /// Darkens a voxel and queues it up for clearing out what it lit.
+    fn darken(&mut self, world_pos: IVec3, level: u8) {
+        self.set_light(world_pos, 0);
+        self.darkened.push_back((world_pos, level));
+        self.light_emitter(world_pos);
+    }
+
+    /// Gives an emissive block its own light back, if it lost it.
+    fn light_emitter(&mut self, world_pos: IVec3) {
+        let Some(emission) = self.block(world_pos).map(|block| self.channel.emission(block)) else {
+            return;
+        };
+
+        if self.light(world_pos).is_some_and(|level| level < emission) {
+            self.set_light(world_pos, emission);
+            self.lit.push_back(world_pos);
+        }
+    }
+
+    /// A voxel changed its type. Whatever light went through it might not anymore,
+    /// and it might let through, or give off, light it didn't before.
+    pub fn voxel_changed(&mut self, world_pos: IVec3) {
+        if let Some(level) = self.light(world_pos).filter(|level| *level > 0) {
+            self.darken(world_pos, level);
+        }
+        self.light_emitter(world_pos);
+
+        for direction in DIRECTIONS {
+            self.lit.push_back(world_pos + direction);
+        }
+    }
+
+    /// A chunk that was lit on its own, see `light_chunk`, was added to the world.
+    /// Light goes both ways across its borders, and sky light it or the chunk below
+    /// got from a wrong guess about what's above is taken away again.
+    pub fn chunk_added(&mut self, chunk_pos: IVec3) {
+        let origin = chunk_pos * CHUNK_SIZE;
+
+        for direction in DIRECTIONS {
+            let axis = if direction.x != 0 { 0 } else if direction.y != 0 { 1 } else { 2 };
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            for a in 0..CHUNK_SIZE {
+                for b in 0..CHUNK_SIZE {
+                    let mut local_pos = IVec3::ZERO;
+                    local_pos[axis] = if direction[axis] > 0 { CHUNK_SIZE - 1 } else { 0 };
+                    local_pos[u] = a;
+                    local_pos[v] = b;
+
+                    let inner = origin + local_pos;
+                    let outer = inner + direction;
+                    let (Some(inner_level), Some(outer_level)) = (self.light(inner), self.light(outer)) else {
+                        continue;
+                    };
+                    let (Some(inner_block), Some(outer_block)) = (self.block(inner), self.block(outer)) else {
+                        continue;
+                    };
+
+                    // Full sky light can only come from straight above
+                    if self.channel == LightChannel::Sky && direction.y != 0 {
+                        let (upper, lower, upper_level, lower_level, lower_block) = if direction.y > 0 {
+                            (outer, inner, outer_level, inner_level, inner_block)
+                        } else {
+                            (inner, outer, inner_level, outer_level, outer_block)
+                        };
+
+                        if lower_level == MAX_LIGHT && self.channel.spread(upper_level, IVec3::NEG_Y, lower_block) < MAX_LIGHT {
+                            self.darken(lower, lower_level);
+                            self.lit.push_back(upper);
+                            continue;
+                        }
+                    }
+
+                    if self.channel.spread(inner_level, direction, outer_block) > outer_level {
+                        self.lit.push_back(inner);
+                    }
+                    if self.channel.spread(outer_level, -direction, inner_block) > inner_level {
+                        self.lit.push_back(outer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the queued updates.
+    /// Returns the loaded chunks whose meshes need to be rebuilt for the new light.
+    pub fn run(mut self) -> HashSet<IVec3> {
+        while let Some((world_pos, level)) = self.darkened.pop_front() {
+            for direction in DIRECTIONS {
+                let next = world_pos + direction;
+                let Some(next_level) = self.light(next).filter(|next_level| *next_level > 0) else {
+                    continue;
+                };
+
+                // Anything dimmer could have gotten its light from here, just like
+                // full sky light right below. Anything else is lit some other way,
+                // and has to light up what just went dark again.
+                let from_here = next_level < level || (self.channel == LightChannel::Sky
+                    && direction == IVec3::NEG_Y && level == MAX_LIGHT && next_level == MAX_LIGHT);
+                if from_here {
+                    self.darken(next, next_level);
+                } else {
+                    self.lit.push_back(next);
+                }
+            }
+        }
+
+        while let Some(world_pos) = self.lit.pop_front() {
+            let Some(level) = self.light(world_pos).filter(|level| *level > 0) else {
+                continue;
+            };
+
+            for direction in DIRECTIONS {
+                let next = world_pos + direction;
+                let (Some(next_level), Some(next_block)) = (self.light(next), self.block(next)) else {
+                    continue;
+                };
+
+                let spread_level = self.channel.spread(level, direction, next_block);
+                if spread_level > next_level {
+                    self.set_light(next, spread_level);
+                    self.lit.push_back(next);
+                }
+            }
+        }
+
+        let chunks = &self.chunks;
+        self.dirty.retain(|chunk_pos| chunks.contains_key(chunk_pos));
+        self.dirty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use bevy::math::IVec3;
+    use crate::voxel::chunk::Chunk;
+    use crate::voxel::registry::{BlockDefinitions, BlockRegistry};
+    use crate::voxel::util::{CHUNK_SIZE, chunk_position, local_position};
+    use crate::voxel::voxel::VoxelType;
+    use crate::worldgen::world::VoxelWorld;
+    use super::{LightChannel, MAX_LIGHT, light_chunk, update_light};
+
+    const GLOWSTONE: VoxelType = VoxelType(13);
+    const TORCH: VoxelType = VoxelType(15);
+    const BLOCK_CHANNELS: [LightChannel; 3] = [LightChannel::Red, LightChannel::Green, LightChannel::Blue];
+
+    fn blocks() -> BlockRegistry {
+        let definitions: BlockDefinitions = ron::de::from_str(include_str!("../../assets/base.blocks.ron")).unwrap();
+        BlockRegistry::new(definitions.atlas, definitions.blocks).unwrap()
+    }
+
+    /// Lights a chunk on its own and adds it to the world, like the generation tasks do.
+    fn add_chunk(voxel_world: &mut VoxelWorld, blocks: &BlockRegistry, mut chunk: Chunk, open_to_sky: bool) {
+        let chunk_pos = chunk.position;
+        light_chunk(&mut chunk, blocks, |_, _| open_to_sky);
+        voxel_world.chunks.insert(chunk_pos, Arc::new(chunk));
+        update_light(&mut voxel_world.chunks, blocks, |update| update.chunk_added(chunk_pos));
+    }
+
+    fn light_at(voxel_world: &VoxelWorld, channel: LightChannel, world_pos: IVec3) -> u8 {
+        voxel_world.chunks[&chunk_position(world_pos)].light(channel, local_position(world_pos))
+    }
+
+    /// Every world position in the chunks.
+    fn world_positions(chunk_positions: &[IVec3]) -> impl Iterator<Item = IVec3> + '_ {
+        chunk_positions.iter().flat_map(|chunk_pos| {
+            (0..CHUNK_SIZE).flat_map(move |y| (0..CHUNK_SIZE).flat_map(move |z| {
+                (0..CHUNK_SIZE).map(move |x| *chunk_pos * CHUNK_SIZE + IVec3::new(x, y, z))
+            }))
+        })
+    }
+
+    #[test]
+    fn sky_light_goes_straight_down_a_shaft() {
+        let blocks = blocks();
+        let mut voxel_world = VoxelWorld::default();
+
+        // Two chunks of stone with a shaft through both, lit before the open chunk above shows up
+        for chunk_y in [-1, 0] {
+            let mut chunk = Chunk::filled(IVec3::new(0, chunk_y, 0), VoxelType::STONE);
+            for y in 0..CHUNK_SIZE {
+                chunk.set(IVec3::new(5, y, 5), VoxelType::AIR);
+            }
+            add_chunk(&mut voxel_world, &blocks, chunk, false);
+        }
+        add_chunk(&mut voxel_world, &blocks, Chunk::new(IVec3::new(0, 1, 0)), true);
+
+        for y in -CHUNK_SIZE..CHUNK_SIZE * 2 {
+            assert_eq!(light_at(&voxel_world, LightChannel::Sky, IVec3::new(5, y, 5)), MAX_LIGHT, "shaft at y {y}");
+        }
+        for y in -CHUNK_SIZE..CHUNK_SIZE {
+            assert_eq!(light_at(&voxel_world, LightChannel::Sky, IVec3::new(6, y, 5)), 0, "stone at y {y}");
+        }
+    }
+
+    #[test]
+    fn wrong_sky_guesses_are_taken_back() {
+        let blocks = blocks();
+        let mut voxel_world = VoxelWorld::default();
+
+        // Guessed to be open, then a solid chunk turns up on top of it
+        add_chunk(&mut voxel_world, &blocks, Chunk::new(IVec3::ZERO), true);
+        add_chunk(&mut voxel_world, &blocks, Chunk::filled(IVec3::Y, VoxelType::STONE), false);
+
+        for world_pos in world_positions(&[IVec3::ZERO]) {
+            assert_eq!(light_at(&voxel_world, LightChannel::Sky, world_pos), 0, "at {world_pos}");
+        }
+    }
+
+    #[test]
+    fn sky_light_falls_off_under_an_overhang() {
+        let blocks = blocks();
+        let covered = IVec3::ZERO;
+        let open = IVec3::X;
+
+        // The light has to come in sideways from the open chunk next to it, whichever comes first
+        for order in [[covered, open], [open, covered]] {
+            let mut voxel_world = VoxelWorld::default();
+            for chunk_pos in order {
+                let mut chunk = Chunk::new(chunk_pos);
+                if chunk_pos == covered {
+                    for x in 0..CHUNK_SIZE {
+                        for z in 0..CHUNK_SIZE {
+                            chunk.set(IVec3::new(x, 20, z), VoxelType::STONE);
+                        }
+                    }
+                }
+                add_chunk(&mut voxel_world, &blocks, chunk, true);
+            }
+
+            for world_pos in world_positions(&[covered]).filter(|world_pos| world_pos.y < 20) {
+                // One less for every voxel away from the open chunk, which starts at x 32
+                let expected = (MAX_LIGHT as i32 - (CHUNK_SIZE - world_pos.x)).max(0) as u8;
+                assert_eq!(light_at(&voxel_world, LightChannel::Sky, world_pos), expected, "at {world_pos}");
+            }
+            for world_pos in world_positions(&[open]) {
+                assert_eq!(light_at(&voxel_world, LightChannel::Sky, world_pos), MAX_LIGHT, "at {world_pos}");
+            }
+        }
+    }
+
+    #[test]
+    fn placing_and_removing_a_block_relights() {
+        let blocks = blocks();
+        let chunk_positions = [IVec3::ZERO, IVec3::X];
+        let mut voxel_world = VoxelWorld::default();
+        for chunk_pos in chunk_positions {
+            add_chunk(&mut voxel_world, &blocks, Chunk::new(chunk_pos), true);
+        }
+
+        // Right on the chunk border
+        let block_pos = IVec3::new(CHUNK_SIZE - 1, 20, 5);
+        voxel_world.set_voxel(&blocks, block_pos, VoxelType::STONE);
+        for world_pos in world_positions(&chunk_positions) {
+            let expected = if world_pos == block_pos {
+                0
+            } else if world_pos.x == block_pos.x && world_pos.z == block_pos.z && world_pos.y < block_pos.y {
+                // In its shadow, lit from the side
+                MAX_LIGHT - 1
+            } else {
+                MAX_LIGHT
+            };
+            assert_eq!(light_at(&voxel_world, LightChannel::Sky, world_pos), expected, "at {world_pos} with the block");
+        }
+
+        voxel_world.set_voxel(&blocks, block_pos, VoxelType::AIR);
+        for world_pos in world_positions(&chunk_positions) {
+            assert_eq!(light_at(&voxel_world, LightChannel::Sky, world_pos), MAX_LIGHT, "at {world_pos} without the block");
+        }
+    }
+
+    /// Two dark chunks next to each other, without any sky light.
+    fn dark_world(blocks: &BlockRegistry) -> (VoxelWorld, [IVec3; 2]) {
+        let chunk_positions = [IVec3::ZERO, IVec3::X];
+        let mut voxel_world = VoxelWorld::default();
+        for chunk_pos in chunk_positions {
+            add_chunk(&mut voxel_world, blocks, Chunk::new(chunk_pos), false);
+        }
+        (voxel_world, chunk_positions)
+    }
+
+    /// The light of each channel a bunch of emitters give a voxel in open air:
+    /// the brightest of them, fading by one for every voxel away.
+    fn expected_light(blocks: &BlockRegistry, emitters: &[(IVec3, VoxelType)], world_pos: IVec3) -> [u8; 3] {
+        std::array::from_fn(|channel| {
+            emitters.iter()
+                .map(|(emitter_pos, voxel_type)| {
+                    let offset = (world_pos - *emitter_pos).abs();
+                    let distance = offset.x + offset.y + offset.z;
+                    (blocks.get(*voxel_type).emission[channel] as i32 - distance).max(0) as u8
+                })
+                .max()
+                .unwrap_or(0)
+        })
+    }
+
+    fn assert_block_light(voxel_world: &VoxelWorld, blocks: &BlockRegistry, chunk_positions: &[IVec3], emitters: &[(IVec3, VoxelType)]) {
+        for world_pos in world_positions(chunk_positions) {
+            let actual = BLOCK_CHANNELS.map(|channel| light_at(voxel_world, channel, world_pos));
+            assert_eq!(actual, expected_light(blocks, emitters, world_pos), "at {world_pos} with {emitters:?}");
+        }
+    }
+
+    #[test]
+    fn removing_an_emitter_clears_its_light() {
+        let blocks = blocks();
+        for voxel_type in [GLOWSTONE, TORCH] {
+            let (mut voxel_world, chunk_positions) = dark_world(&blocks);
+
+            // Close to the border, so the light reaches into the other chunk
+            let emitter = (IVec3::new(CHUNK_SIZE - 3, 10, 10), voxel_type);
+            voxel_world.set_voxel(&blocks, emitter.0, voxel_type);
+            assert_block_light(&voxel_world, &blocks, &chunk_positions, &[emitter]);
+
+            voxel_world.set_voxel(&blocks, emitter.0, VoxelType::AIR);
+            assert_block_light(&voxel_world, &blocks, &chunk_positions, &[]);
+        }
+    }
+
+    #[test]
+    fn overlapping_emitters_keep_the_brighter_light() {
+        let blocks = blocks();
+        // On either side of the chunk border, close enough for their light to overlap.
+        // Glowstone is brighter in every channel, but the torch wins close to itself.
+        let glowstone = (IVec3::new(CHUNK_SIZE - 3, 10, 10), GLOWSTONE);
+        let torch = (IVec3::new(CHUNK_SIZE + 2, 12, 10), TORCH);
+
+        for (removed, kept) in [(glowstone, torch), (torch, glowstone)] {
+            let (mut voxel_world, chunk_positions) = dark_world(&blocks);
+            voxel_world.set_voxel(&blocks, glowstone.0, glowstone.1);
+            voxel_world.set_voxel(&blocks, torch.0, torch.1);
+            assert_block_light(&voxel_world, &blocks, &chunk_positions, &[glowstone, torch]);
+
+            voxel_world.set_voxel(&blocks, removed.0, VoxelType::AIR);
+            assert_block_light(&voxel_world, &blocks, &chunk_positions, &[kept]);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::{HashSet, VecDeque};
+use bevy::prelude::*;
+use crate::voxel::registry::BlockRegistry;
+use crate::voxel::tasks::ChunkTasks;
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::world::VoxelWorld;
+
+/// The level of a liquid that never runs dry. Liquids placed by hand
+/// or by world generation are sources.
+pub const SOURCE_LEVEL: u8 = 0;
+
+/// Flowing liquid is one level further from its source for every voxel it spreads
+/// sideways, and doesn't spread any further than this.
+pub const MAX_FLOW_LEVEL: u8 = 7;
+
+/// The level of liquid falling down, no matter how far it came from.
+const FALLING_LEVEL: u8 = 1;
+
+/// How high liquid of a level stands in its voxel, from 0 to 1.
+/// Even sources leave a little room, so still water sits a bit below the ground around it.
+/// Levels past `MAX_FLOW_LEVEL` are as low as liquid gets.
+pub fn liquid_height(level: u8) -> f32 {
+    (MAX_FLOW_LEVEL + 1 - level.min(MAX_FLOW_LEVEL)) as f32 / (MAX_FLOW_LEVEL + 2) as f32
+}
+
+const DIRECTIONS: [IVec3; 6] = [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z];
+const HORIZONTAL: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+/// Makes liquids flow, one step every tick.
+///
+/// Liquid falls into air below it, and spreads sideways when it can't fall,
+/// getting a level further from its source every voxel until it reaches `MAX_FLOW_LEVEL`.
+/// Flowing liquid that loses what fed it keeps getting further from a source
+/// it doesn't have, until it's gone.
+///
+/// Only voxels that changed, and their neighbours, are looked at. Liquid that was
+/// generated stays where it is until something next to it changes.
+#[derive(Resource)]
+pub struct LiquidSimulation {
+    /// Voxels whose liquid might have to change, oldest first.
+    pending: VecDeque<IVec3>,
+    /// Everything in `pending`, so no voxel is in there twice.
+    queued: HashSet<IVec3>,
+    /// How often liquids move.
+    pub tick: Timer,
+    /// The most voxels looked at in a tick. A big flood takes a few ticks longer
+    /// instead of stalling a frame, the rest waits for the next tick.
+    pub max_updates_per_tick: usize,
+}
+
+impl Default for LiquidSimulation {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            queued: HashSet::new(),
+            tick: Timer::from_seconds(0.25, TimerMode::Repeating),
+            max_updates_per_tick: 512,
+        }
+    }
+}
+
+impl LiquidSimulation {
+    /// A voxel changed, so liquid might start or stop flowing into it, or out of it.
+    pub fn voxel_changed(&mut self, world_pos: IVec3) {
+        self.queue(world_pos);
+        for direction in DIRECTIONS {
+            self.queue(world_pos + direction);
+        }
+    }
+
+    fn queue(&mut self, world_pos: IVec3) {
+        if self.queued.insert(world_pos) {
+            self.pending.push_back(world_pos);
+        }
+    }
+
+    /// How many voxels are waiting to be looked at.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Looks at up to `max_updates_per_tick` of the pending voxels and moves their liquid.
+    /// Returns the loaded chunks whose meshes are out of date.
+    pub fn step(&mut self, voxel_world: &mut VoxelWorld, blocks: &BlockRegistry) -> HashSet<IVec3> {
+        let count = self.pending.len().min(self.max_updates_per_tick);
+        let batch: Vec<IVec3> = self.pending.drain(..count).collect();
+        for world_pos in batch.iter() {
+            self.queued.remove(world_pos);
+        }
+
+        // Every change is worked out before any is made, so liquid moves a voxel
+        // a tick no matter which order the voxels come in
+        let changes: Vec<(IVec3, (VoxelType, u8))> = batch.into_iter()
+            .filter_map(|world_pos| next_state(voxel_world, blocks, world_pos).map(|next| (world_pos, next)))
+            .collect();
+
+        let mut dirty = HashSet::new();
+        for (world_pos, (voxel_type, level)) in changes {
+            if voxel_world.get_voxel(world_pos) != Some(voxel_type) {
+                dirty.extend(voxel_world.set_voxel(blocks, world_pos, voxel_type));
+            }
+            dirty.extend(voxel_world.set_liquid_level(world_pos, level));
+            self.voxel_changed(world_pos);
+        }
+        dirty
+    }
+}
+
+/// The liquid that flows into a voxel and the level it has there, if any.
+/// Only `liquid` can flow into a voxel that already holds it, anything can flow into air.
+fn inflow(
+    voxel_world: &VoxelWorld,
+    blocks: &BlockRegistry,
+    world_pos: IVec3,
+    liquid: Option<VoxelType>,
+) -> Option<(VoxelType, u8)> {
+    let liquid_at = |world_pos: IVec3| {
+        let voxel_type = voxel_world.get_voxel(world_pos)?;
+        let level = voxel_world.liquid_level(world_pos)?;
+        let flows = blocks.get(voxel_type).is_liquid() && liquid.is_none_or(|liquid| liquid == voxel_type);
+        flows.then_some((voxel_type, level))
+    };
+
+    if let Some((voxel_type, _)) = liquid_at(world_pos + IVec3::Y) {
+        return Some((voxel_type, FALLING_LEVEL));
+    }
+
+    HORIZONTAL.iter()
+        .filter_map(|direction| {
+            let from = world_pos + *direction;
+            let (voxel_type, level) = liquid_at(from)?;
+
+            // Liquid that can still fall doesn't spread sideways. It can fall into air,
+            // and into flowing liquid, but not into a source
+            let below = blocks.get(voxel_world.get_voxel(from - IVec3::Y)?);
+            let resting = below.is_visible()
+                && !(below.is_liquid() && voxel_world.liquid_level(from - IVec3::Y)? != SOURCE_LEVEL);
+            (level < MAX_FLOW_LEVEL && resting).then_some((voxel_type, level + 1))
+        })
+        .min_by_key(|(_, level)| *level)
+}
+
+/// What a voxel turns into this tick, if it changes at all.
+fn next_state(voxel_world: &VoxelWorld, blocks: &BlockRegistry, world_pos: IVec3) -> Option<(VoxelType, u8)> {
+    let voxel_type = voxel_world.get_voxel(world_pos)?;
+    let block = blocks.get(voxel_type);
+
+    if block.is_liquid() {
+        let level = voxel_world.liquid_level(world_pos)?;
+        if level == SOURCE_LEVEL {
+            return None;
+        }
+
+        match inflow(voxel_world, blocks, world_pos, Some(voxel_type)) {
+            Some((_, next_level)) if next_level == level => None,
+            Some(next) => Some(next),
+            None => Some((VoxelType::AIR, SOURCE_LEVEL)),
+        }
+    } else if !block.is_visible() {
+        inflow(voxel_world, blocks, world_pos, None)
+    } else {
+        None
+    }
+}
+
+/// Moves the liquids one step, and remeshes the chunks they moved in.
+pub fn simulate_liquids(
+    time: Res<Time>,
+    blocks: Res<BlockRegistry>,
+    mut liquids: ResMut<LiquidSimulation>,
+    mut voxel_world: ResMut<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+) {
+    if !liquids.tick.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    for chunk_pos in liquids.step(&mut voxel_world, &blocks) {
+        chunk_tasks.queue_remesh(chunk_pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use bevy::math::IVec3;
+    use crate::voxel::chunk::Chunk;
+    use crate::voxel::registry::{BlockDefinitions, BlockRegistry};
+    use crate::voxel::util::CHUNK_SIZE;
+    use crate::voxel::voxel::VoxelType;
+    use crate::worldgen::world::VoxelWorld;
+    use super::{FALLING_LEVEL, LiquidSimulation, MAX_FLOW_LEVEL, SOURCE_LEVEL, next_state};
+
+    const LAVA: VoxelType = VoxelType(14);
+
+    fn blocks() -> BlockRegistry {
+        let definitions: BlockDefinitions = ron::de::from_str(include_str!("../../assets/base.blocks.ron")).unwrap();
+        BlockRegistry::new(definitions.atlas, definitions.blocks).unwrap()
+    }
+
+    /// A single chunk of air with a stone floor at y 0.
+    fn floor_world() -> VoxelWorld {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set(IVec3::new(x, 0, z), VoxelType::STONE);
+            }
+        }
+
+        let mut voxel_world = VoxelWorld::default();
+        voxel_world.chunks.insert(IVec3::ZERO, Arc::new(chunk));
+        voxel_world
+    }
+
+    fn place(voxel_world: &mut VoxelWorld, blocks: &BlockRegistry, world_pos: IVec3, voxel_type: VoxelType, level: u8) {
+        voxel_world.set_voxel(blocks, world_pos, voxel_type);
+        voxel_world.set_liquid_level(world_pos, level);
+    }
+
+    #[test]
+    fn sources_and_solids_stay() {
+        let blocks = blocks();
+        let mut voxel_world = floor_world();
+        place(&mut voxel_world, &blocks, IVec3::new(5, 1, 5), VoxelType::WATER, SOURCE_LEVEL);
+
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 1, 5)), None);
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 0, 5)), None);
+        // Not loaded
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(-1, 1, 5)), None);
+    }
+
+    #[test]
+    fn resting_liquid_spreads_one_level_further() {
+        let blocks = blocks();
+        let mut voxel_world = floor_world();
+        place(&mut voxel_world, &blocks, IVec3::new(5, 1, 5), VoxelType::WATER, SOURCE_LEVEL);
+        place(&mut voxel_world, &blocks, IVec3::new(10, 1, 5), VoxelType::WATER, 3);
+        place(&mut voxel_world, &blocks, IVec3::new(15, 1, 5), VoxelType::WATER, MAX_FLOW_LEVEL);
+
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(6, 1, 5)), Some((VoxelType::WATER, 1)));
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(10, 1, 6)), Some((VoxelType::WATER, 4)));
+        // As far as it goes
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(16, 1, 5)), None);
+        // Nothing spreads upwards
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 2, 5)), None);
+    }
+
+    #[test]
+    fn liquid_falls_before_it_spreads() {
+        let blocks = blocks();
+        let mut voxel_world = floor_world();
+        place(&mut voxel_world, &blocks, IVec3::new(5, 10, 5), VoxelType::WATER, SOURCE_LEVEL);
+
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 9, 5)), Some((VoxelType::WATER, FALLING_LEVEL)));
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(6, 10, 5)), None);
+
+        // Falling liquid is always the same level, however far it came from
+        place(&mut voxel_world, &blocks, IVec3::new(5, 9, 5), VoxelType::WATER, 6);
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 9, 5)), Some((VoxelType::WATER, FALLING_LEVEL)));
+    }
+
+    #[test]
+    fn flowing_liquid_follows_its_closest_feed() {
+        let blocks = blocks();
+        let mut voxel_world = floor_world();
+
+        // Nothing feeds it anymore
+        place(&mut voxel_world, &blocks, IVec3::new(5, 1, 5), VoxelType::WATER, 3);
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 1, 5)), Some((VoxelType::AIR, SOURCE_LEVEL)));
+
+        // A source right next to it
+        place(&mut voxel_world, &blocks, IVec3::new(4, 1, 5), VoxelType::WATER, SOURCE_LEVEL);
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 1, 5)), Some((VoxelType::WATER, 1)));
+        place(&mut voxel_world, &blocks, IVec3::new(5, 1, 5), VoxelType::WATER, 1);
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(5, 1, 5)), None);
+
+        // Other liquids don't feed it
+        place(&mut voxel_world, &blocks, IVec3::new(10, 1, 5), LAVA, 2);
+        place(&mut voxel_world, &blocks, IVec3::new(11, 1, 5), VoxelType::WATER, SOURCE_LEVEL);
+        assert_eq!(next_state(&voxel_world, &blocks, IVec3::new(10, 1, 5)), Some((VoxelType::AIR, SOURCE_LEVEL)));
+    }
+
+    #[test]
+    fn sources_spread_and_drain_again() {
+        let blocks = blocks();
+        let mut voxel_world = floor_world();
+        let mut liquids = LiquidSimulation::default();
+        let source = IVec3::new(16, 1, 16);
+
+        let settle = |voxel_world: &mut VoxelWorld, liquids: &mut LiquidSimulation| {
+            for _ in 0..100 {
+                if liquids.pending() == 0 {
+                    return;
+                }
+                liquids.step(voxel_world, &blocks);
+            }
+            panic!("liquids never settled");
+        };
+
+        voxel_world.set_voxel(&blocks, source, VoxelType::WATER);
+        liquids.voxel_changed(source);
+        settle(&mut voxel_world, &mut liquids);
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let world_pos = IVec3::new(x, 1, z);
+                let distance = (x - source.x).abs() + (z - source.z).abs();
+                if distance <= MAX_FLOW_LEVEL as i32 {
+                    assert_eq!(voxel_world.get_voxel(world_pos), Some(VoxelType::WATER), "at {world_pos}");
+                    assert_eq!(voxel_world.liquid_level(world_pos), Some(distance as u8), "at {world_pos}");
+                } else {
+                    assert_eq!(voxel_world.get_voxel(world_pos), Some(VoxelType::AIR), "at {world_pos}");
+                }
+            }
+        }
+
+        voxel_world.set_voxel(&blocks, source, VoxelType::AIR);
+        liquids.voxel_changed(source);
+        settle(&mut voxel_world, &mut liquids);
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let world_pos = IVec3::new(x, 1, z);
+                assert_eq!(voxel_world.get_voxel(world_pos), Some(VoxelType::AIR), "at {world_pos}");
+            }
+        }
+    }
+
+    #[test]
+    fn steps_are_limited() {
+        let blocks = blocks();
+        let mut voxel_world = floor_world();
+        let mut liquids = LiquidSimulation::default();
+
+        // Nothing changes in there, so nothing new gets queued
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                liquids.voxel_changed(IVec3::new(x, 0, z));
+            }
+        }
+        let pending = liquids.pending();
+        assert!(pending > liquids.max_updates_per_tick * 2);
+
+        liquids.step(&mut voxel_world, &blocks);
+        assert_eq!(liquids.pending(), pending - liquids.max_updates_per_tick);
+        liquids.step(&mut voxel_world, &blocks);
+        assert_eq!(liquids.pending(), pending - liquids.max_updates_per_tick * 2);
+    }
+}
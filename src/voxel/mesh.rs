@@ -1,6 +1,7 @@
 use bevy::math::IVec3;
 use rand::Rng;
-use crate::voxel::voxel::VoxelType;
+use crate::voxel::atlas::FaceUvs;
+use crate::voxel::light::{MAX_LIGHT, VertexLight};
 
 /// Holds everything relevant for a mesh.
 /// Later turned into a Bevy mesh, using all data.
@@ -9,8 +10,18 @@ pub struct Mesh {
     pub normals: Vec<[f32; 3]>,
     pub indices: Vec<u32>,
     pub colors: Vec<[f32; 4]>,
+    /// Counted in tiles, see `FaceUvs`.
     pub uvs: Vec<[f32; 2]>,
+    /// Where in the atlas the tile of every vertex starts.
+    pub tiles: Vec<[f32; 2]>,
     pub aos: Vec<u32>,
+    /// Light of every vertex, every channel from 0 to `MAX_LIGHT`.
+    pub lights: Vec<VertexLight>,
+    /// Which way the liquid at every vertex flows, in world space. Only liquid meshes have these.
+    pub flows: Vec<[f32; 3]>,
+    /// How many voxels of liquid are under every vertex, its own voxel included.
+    /// Only liquid meshes have these.
+    pub depths: Vec<f32>,
 }
 
 impl Mesh {
@@ -54,24 +65,43 @@ impl Mesh {
         self.normals.extend([normal; 4])
     }
 
-    pub fn set_uvs(&mut self, voxel_type: &VoxelType) {
-        self.uvs.extend(voxel_type.type_to_uvs());
+    pub fn set_uvs(&mut self, face_uvs: FaceUvs) {
+        self.uvs.extend(face_uvs.uvs);
+        self.tiles.extend([face_uvs.tile; 4]);
     }
 
-    pub fn add_ao_color(&mut self, alpha: f32) {
-        self.colors = self.vertices
+    /// Turns the AO and light of every vertex into its colour.
+    pub fn add_light_color(&mut self, alpha: f32) {
+        self.colors = self.aos
             .iter()
-            .enumerate()
-            .map(|(i, _)| match self.aos[i] {
-                0 => [0.1, 0.1, 0.1, alpha],
-                1 => [0.3, 0.3, 0.3, alpha],
-                2 => [0.5, 0.5, 0.5, alpha],
-                3 => [1.0, 1.0, 1.0, alpha],
-                _ => [1.0, 1.0, 1.0, alpha],
+            .zip(self.lights.iter())
+            .map(|(ao, light)| {
+                let [red, green, blue] = vertex_color(*ao, *light);
+                [red, green, blue, alpha]
             }).collect();
     }
 }
 
+/// The colour AO and light give a vertex, without alpha.
+pub fn vertex_color(ao: u32, light: VertexLight) -> [f32; 3] {
+    let ao = match ao {
+        0 => 0.1,
+        1 => 0.3,
+        2 => 0.5,
+        _ => 1.0,
+    };
+
+    // Sky light is white, and block light can't make anything darker than it
+    let [sky, red, green, blue] = light;
+    [red, green, blue].map(|level| ao * light_brightness(sky.max(level)))
+}
+
+/// How bright a light level looks. Every level is a bit darker than the one above it,
+/// and even the darkest caves keep a little light so they're not pitch black.
+fn light_brightness(level: f32) -> f32 {
+    0.8f32.powf(MAX_LIGHT as f32 - level).max(0.04)
+}
+
 impl Default for Mesh {
     fn default() -> Self {
         Self {
@@ -80,7 +110,11 @@ impl Default for Mesh {
             indices: Vec::new(),
             colors: Vec::new(),
             uvs: Vec::new(),
-            aos: Vec::new()
+            tiles: Vec::new(),
+            aos: Vec::new(),
+            lights: Vec::new(),
+            flows: Vec::new(),
+            depths: Vec::new(),
         }
     }
 }
\ No newline at end of file
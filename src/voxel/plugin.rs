@@ -1,9 +1,16 @@
-use bevy::pbr::{NotShadowCaster, NotShadowReceiver};
+use bevy::app::AppExit;
 use bevy::prelude::*;
-use bevy::render::mesh::{Indices, PrimitiveTopology};
-use bevy::render::render_asset::RenderAssetUsages;
-use crate::global::Settings;
-use crate::voxel::chunk_mesh::ChunkMesh;
+use bevy::render::texture::{ImageLoaderSettings, ImageSampler};
+use crate::voxel::block_material::{AtlasTiling, BlockMaterial, update_block_atlas};
+use crate::voxel::liquid::{LiquidSimulation, simulate_liquids};
+use crate::voxel::registry::{BlockDefinitions, BlockDefinitionsHandle, BlockDefinitionsLoader, BlockRegistry, build_block_registry};
+use crate::voxel::streaming::{ChunkStreaming, stream_chunks};
+use crate::voxel::water::{WaterMaterial, apply_water_setting, update_water_atlas};
+use crate::voxel::tasks::{ChunkEntities, ChunkTasks, poll_load_tasks, poll_mesh_tasks, poll_stage_tasks, queue_load_tasks, queue_mesh_tasks, queue_stage_tasks, remesh_on_meshing_mode};
+use crate::worldgen::biome::BiomeRegistry;
+use crate::worldgen::ores::OreRegistry;
+use crate::worldgen::config::WorldGenConfig;
+use crate::worldgen::generator::ChunkGenerators;
 use crate::worldgen::world::VoxelWorld;
 
 /// Handles the logic and all the fun things relating
@@ -12,78 +19,136 @@ pub struct VoxelWorldPlugin;
 
 impl Plugin for VoxelWorldPlugin {
     fn build(&self,  app: &mut App) {
-        app.insert_resource(VoxelWorld::default())
-            .add_systems(Startup, setup_world);
+        // Whoever builds the app can pick a config, otherwise it comes from the command line
+        if !app.world.contains_resource::<WorldGenConfig>() {
+            let config = WorldGenConfig::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+                error!("{err}, using the default world generation config instead");
+                WorldGenConfig::default()
+            });
+            app.insert_resource(config);
+        }
+
+        let voxel_world = VoxelWorld::for_config(app.world.resource::<WorldGenConfig>());
+        app.insert_resource(voxel_world)
+            .init_resource::<ChunkTasks>()
+            .init_resource::<ChunkEntities>()
+            .init_resource::<ChunkStreaming>()
+            .init_resource::<LiquidSimulation>()
+            .init_resource::<BiomeRegistry>()
+            .init_resource::<OreRegistry>()
+            .init_resource::<ChunkGenerators>()
+            .add_plugins(MaterialPlugin::<BlockMaterial>::default())
+            .add_plugins(MaterialPlugin::<WaterMaterial>::default())
+            .init_asset::<BlockDefinitions>()
+            .init_asset_loader::<BlockDefinitionsLoader>()
+            .add_systems(Startup, (setup_world, setup_generator))
+            .add_systems(Update, build_block_registry.run_if(resource_exists::<BlockDefinitionsHandle>))
+            // Nothing can be meshed until we know what the blocks look like
+            .add_systems(Update, (
+                stream_chunks,
+                poll_load_tasks,
+                poll_stage_tasks,
+                remesh_on_meshing_mode,
+                queue_mesh_tasks,
+                poll_mesh_tasks,
+                queue_load_tasks,
+                queue_stage_tasks,
+            ).chain().run_if(resource_exists::<BlockRegistry>))
+            .add_systems(Update, simulate_liquids.run_if(resource_exists::<BlockRegistry>))
+            .add_systems(Update, (
+                (update_block_atlas, update_water_atlas).run_if(resource_exists_and_changed::<BlockRegistry>),
+                apply_water_setting,
+            ))
+            .add_systems(Last, save_on_exit);
     }
 }
 
-fn setup_world(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+/// The materials every chunk mesh shares.
+#[derive(Resource)]
+pub struct ChunkMaterials {
+    pub solid: Handle<BlockMaterial>,
+    /// Liquids when `Settings::water_shader` is off, and liquids that give off light either way.
+    pub liquid: Handle<BlockMaterial>,
+    /// Liquids when `Settings::water_shader` is on.
+    pub water: Handle<WaterMaterial>,
+    pub cutout: Handle<BlockMaterial>,
+}
+
+/// Makes sure nothing is lost when the app closes.
+fn save_on_exit(
+    mut exit_events: EventReader<AppExit>,
     mut voxel_world: ResMut<VoxelWorld>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    settings: Res<Settings>
 ) {
-    for x in 0..20 {
-        for y in -5..7 {
-            for z in 0..20 {
-                let chunk_pos = IVec3::new(x, y, z);
-                voxel_world.generate_chunk(chunk_pos);
-            }
+    if exit_events.read().next().is_some() {
+        if let Err(err) = voxel_world.save() {
+            error!("Could not save the world: {err}");
         }
     }
+}
 
-    let texture: Handle<Image> = asset_server.load("voxel_atlas.png");
-    for (_chunk_pos, chunk) in &voxel_world.chunks {
-        let mut chunk_mesh = ChunkMesh::default();
-        chunk_mesh.build_chunk_mesh(&chunk, &voxel_world.chunks, &settings);
+/// Builds the ChunkGenerator the config asks for.
+fn setup_generator(world: &mut World) {
+    let config = world.resource::<WorldGenConfig>();
+    info!(
+        "Generating the world with the {} generator, seed {} and the {} preset",
+        config.generator, config.seed, config.preset,
+    );
 
-        let mesh_handle = meshes.add(setup_bevy_mesh(chunk_mesh.mesh, false));
+    let generators = world.resource::<ChunkGenerators>();
+    let generator = generators.build(&config.generator, world).unwrap_or_else(|| {
+        let names: Vec<&str> = generators.names().collect();
+        error!("There is no generator called \"{}\", pick one of {}. Using \"noise\" instead", config.generator, names.join(", "));
+        generators.build("noise", world).expect("the noise generator is always registered")
+    });
 
-        commands.spawn(PbrBundle {
-            mesh: mesh_handle,
-            material: materials.add(StandardMaterial {
-                base_color_texture: Some(texture.clone()),
-                ..default()
-            }),
-            ..default()
-        });
+    world.resource_mut::<VoxelWorld>().generator = generator;
+}
 
-        if !chunk_mesh.liquid_mesh.vertices.is_empty() {
-            let mesh_handle = meshes.add(setup_bevy_mesh(chunk_mesh.liquid_mesh, true));
+fn setup_world(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<BlockMaterial>>,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
+) {
+    commands.insert_resource(BlockDefinitionsHandle(asset_server.load("base.blocks.ron")));
 
-            commands.spawn((PbrBundle {
-                mesh: mesh_handle,
-                material: materials.add(StandardMaterial {
-                    base_color: Color::rgba(1., 1., 1., 0.9),
-                    base_color_texture: Some(texture.clone()),
-                    alpha_mode: AlphaMode::Blend,
-                    cull_mode: None,
-                    ..default()
-                }),
+    // Tiles are only a few texels wide, filtering would blur them into mush
+    let texture: Handle<Image> = asset_server.load_with_settings("voxel_atlas.png", |settings: &mut ImageLoaderSettings| {
+        settings.sampler = ImageSampler::nearest();
+    });
+    commands.insert_resource(ChunkMaterials {
+        solid: materials.add(BlockMaterial {
+            base: StandardMaterial {
+                base_color_texture: Some(texture.clone()),
                 ..default()
-            }, NotShadowReceiver, NotShadowCaster));
-        }
-    }
+            },
+            extension: AtlasTiling::default(),
+        }),
+        liquid: materials.add(BlockMaterial {
+            base: StandardMaterial {
+                base_color: Color::rgba(1., 1., 1., 0.9),
+                base_color_texture: Some(texture.clone()),
+                alpha_mode: AlphaMode::Blend,
+                cull_mode: None,
+                ..default()
+            },
+            extension: AtlasTiling::default(),
+        }),
+        water: water_materials.add(WaterMaterial::new(texture.clone())),
+        cutout: materials.add(BlockMaterial {
+            base: StandardMaterial {
+                base_color_texture: Some(texture.clone()),
+                alpha_mode: AlphaMode::Mask(0.5),
+                // Leaves are seen through from both sides
+                cull_mode: None,
+                ..default()
+            },
+            extension: AtlasTiling::default(),
+        }),
+    });
 
     commands.insert_resource(AmbientLight {
         color: Color::rgb(0.98, 0.95, 0.82),
         brightness: 1000.0,
     });
 }
-
-fn setup_bevy_mesh(voxel_mesh: crate::voxel::mesh::Mesh, liquid: bool) -> Mesh {
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
-
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, voxel_mesh.vertices.clone());
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, voxel_mesh.normals);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, voxel_mesh.uvs);
-    if !liquid {
-        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, voxel_mesh.colors);
-    }
-
-    mesh.insert_indices(Indices::U32(voxel_mesh.indices));
-
-    mesh
-}
\ No newline at end of file
@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use crate::voxel::registry::BlockRegistry;
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::world::VoxelWorld;
+
+/// What a ray ran into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    /// World position of the voxel that was hit.
+    pub position: IVec3,
+    pub voxel_type: VoxelType,
+    /// The normal of the face the ray entered through.
+    /// Zero if the ray started inside the voxel.
+    pub normal: IVec3,
+    /// How far along the ray the hit happened.
+    pub distance: f32,
+}
+
+impl VoxelWorld {
+    /// Walks a ray through the voxel grid, one voxel at a time (DDA),
+    /// and returns the first visible voxel it runs into.
+    /// Chunks that aren't loaded are treated as empty.
+    ///
+    /// * `blocks`: The BlockRegistry, to tell which voxels are visible or liquid.
+    /// * `origin`: Where the ray starts, in world space.
+    /// * `direction`: Which way the ray goes. Doesn't have to be normalized.
+    /// * `max_distance`: How far the ray goes before giving up. Has to be finite and above zero.
+    /// * `ignore_liquids`: Lets the ray go through liquids, like WATER.
+    pub fn raycast(
+        &self,
+        blocks: &BlockRegistry,
+        origin: Vec3,
+        direction: Vec3,
+        max_distance: f32,
+        ignore_liquids: bool,
+    ) -> Option<RaycastHit> {
+        // The walk only ends once it's past max_distance, which never happens with NaN or infinity
+        if !(max_distance.is_finite() && max_distance > 0.0) || !origin.is_finite() {
+            return None;
+        }
+
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        let mut voxel_pos = origin.floor().as_ivec3();
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+
+        // Which way we move along each axis, how far along the ray we have to go
+        // to cross a whole voxel, and how far until we cross the next boundary.
+        let mut step = IVec3::ZERO;
+        let mut t_delta = Vec3::splat(f32::INFINITY);
+        let mut t_max = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                t_delta[axis] = 1.0 / direction[axis];
+                t_max[axis] = (voxel_pos[axis] as f32 + 1.0 - origin[axis]) * t_delta[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                t_delta[axis] = -1.0 / direction[axis];
+                t_max[axis] = (origin[axis] - voxel_pos[axis] as f32) * t_delta[axis];
+            }
+        }
+
+        loop {
+            if let Some(voxel_type) = self.get_voxel(voxel_pos) {
+                let block = blocks.get(voxel_type);
+                if block.is_visible() && !(ignore_liquids && block.is_liquid()) {
+                    return Some(RaycastHit {
+                        position: voxel_pos,
+                        voxel_type,
+                        normal,
+                        distance,
+                    });
+                }
+            }
+
+            // Step over whichever boundary is closest
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+
+            distance = t_max[axis];
+            if distance > max_distance {
+                return None;
+            }
+
+            voxel_pos[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+use std::sync::Arc;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::asset::io::Reader;
+use bevy::math::IVec3;
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+use crate::voxel::atlas::{AtlasLayout, FaceUvs};
+use crate::voxel::voxel::VoxelType;
+
+/// Which mesh a block ends up in.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum RenderLayer {
+    /// Not drawn at all, like air.
+    Invisible,
+    Opaque,
+    /// Transparent, drawn in the liquid mesh.
+    Liquid,
+    /// Drawn in the cutout mesh. Texels with low alpha are cut out
+    /// completely instead of blended, like the gaps between leaves.
+    Cutout,
+}
+
+/// Atlas tiles used for each face of a block, as (column, row).
+/// The four sides use `side`, unless they have a tile of their own.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct BlockTextures {
+    pub top: (u32, u32),
+    pub bottom: (u32, u32),
+    pub side: (u32, u32),
+    /// Facing +X.
+    #[serde(default)]
+    pub east: Option<(u32, u32)>,
+    /// Facing -X.
+    #[serde(default)]
+    pub west: Option<(u32, u32)>,
+    /// Facing +Z.
+    #[serde(default)]
+    pub south: Option<(u32, u32)>,
+    /// Facing -Z.
+    #[serde(default)]
+    pub north: Option<(u32, u32)>,
+}
+
+impl BlockTextures {
+    /// The tile for the face pointing towards `normal`.
+    pub fn tile(&self, normal: IVec3) -> (u32, u32) {
+        let side = match normal {
+            IVec3::Y => return self.top,
+            IVec3::NEG_Y => return self.bottom,
+            IVec3::X => self.east,
+            IVec3::NEG_X => self.west,
+            IVec3::Z => self.south,
+            _ => self.north,
+        };
+        side.unwrap_or(self.side)
+    }
+
+    fn tiles(&self) -> impl Iterator<Item = (u32, u32)> {
+        [Some(self.top), Some(self.bottom), Some(self.side), self.east, self.west, self.south, self.north]
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// Everything there is to know about a block type.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockDefinition {
+    pub name: String,
+    pub id: u16,
+    /// Opaque blocks hide the faces of the blocks next to them.
+    pub opaque: bool,
+    #[serde(default)]
+    pub liquid: bool,
+    /// How much light fades going through the block, on top of the 1 every voxel takes.
+    /// Full sky light only goes straight down unchanged through blocks that don't absorb any.
+    /// Opaque blocks stop light completely.
+    #[serde(default)]
+    pub light_absorption: u8,
+    /// The light the block gives off, as red, green and blue levels from 0 to 15.
+    /// White light has all three the same.
+    #[serde(default)]
+    pub emission: [u8; 3],
+    #[serde(default)]
+    pub textures: BlockTextures,
+    pub render_layer: RenderLayer,
+}
+
+impl BlockDefinition {
+    /// If something is AIR, it's "invisible".
+    pub fn is_visible(&self) -> bool {
+        self.render_layer != RenderLayer::Invisible
+    }
+
+    pub fn is_liquid(&self) -> bool {
+        self.liquid
+    }
+
+    /// Some blocks are technically "visible"
+    /// but should still have things render behind them.
+    /// Things like WATER, etc.
+    pub fn should_render(&self) -> bool {
+        !self.opaque
+    }
+}
+
+/// The contents of a `.blocks.ron` file.
+#[derive(Asset, TypePath, Debug, Deserialize)]
+pub struct BlockDefinitions {
+    pub atlas: AtlasLayout,
+    pub blocks: Vec<BlockDefinition>,
+}
+
+#[derive(Default)]
+pub struct BlockDefinitionsLoader;
+
+impl AssetLoader for BlockDefinitionsLoader {
+    type Asset = BlockDefinitions;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes::<BlockDefinitions>(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blocks.ron"]
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockRegistryError {
+    DuplicateId(u16),
+    MissingBuiltIn(u16),
+    TileOutOfBounds(String, (u32, u32)),
+}
+
+impl fmt::Display for BlockRegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockRegistryError::DuplicateId(id) => write!(f, "block id {id} is defined more than once"),
+            BlockRegistryError::MissingBuiltIn(id) => write!(f, "built-in block id {id} is not defined"),
+            BlockRegistryError::TileOutOfBounds(name, tile) => write!(f, "block {name} uses tile {tile:?}, which is outside the atlas"),
+        }
+    }
+}
+
+/// All the block types in the game, looked up by id.
+/// Cheap to clone, so meshing tasks can take it along.
+#[derive(Resource, Clone)]
+pub struct BlockRegistry {
+    /// Indexed by block id. Ids don't have to be contiguous.
+    blocks: Arc<Vec<Option<BlockDefinition>>>,
+    pub atlas: AtlasLayout,
+}
+
+impl BlockRegistry {
+    pub fn new(atlas: AtlasLayout, definitions: Vec<BlockDefinition>) -> Result<Self, BlockRegistryError> {
+        let mut blocks: Vec<Option<BlockDefinition>> = Vec::new();
+        for definition in definitions {
+            if let Some(tile) = definition.textures.tiles().find(|tile| !atlas.contains(*tile)) {
+                return Err(BlockRegistryError::TileOutOfBounds(definition.name, tile));
+            }
+
+            let id = definition.id as usize;
+            if blocks.len() <= id {
+                blocks.resize(id + 1, None);
+            }
+
+            if blocks[id].is_some() {
+                return Err(BlockRegistryError::DuplicateId(definition.id));
+            }
+            blocks[id] = Some(definition);
+        }
+
+        for built_in in VoxelType::BUILT_IN {
+            if blocks.get(built_in.0 as usize).and_then(Option::as_ref).is_none() {
+                return Err(BlockRegistryError::MissingBuiltIn(built_in.0));
+            }
+        }
+
+        Ok(Self {
+            blocks: Arc::new(blocks),
+            atlas,
+        })
+    }
+
+    /// The definition of a block type.
+    /// Ids that aren't defined, say from an old save, are treated as air.
+    pub fn get(&self, voxel_type: VoxelType) -> &BlockDefinition {
+        self.blocks.get(voxel_type.0 as usize)
+            .and_then(Option::as_ref)
+            .unwrap_or_else(|| self.blocks[VoxelType::AIR.0 as usize].as_ref().unwrap())
+    }
+
+    /// The UVs for the face of a block pointing towards `normal`, `size` voxels big.
+    pub fn face_uvs(&self, voxel_type: VoxelType, normal: IVec3, size: [f32; 2]) -> FaceUvs {
+        let tile = self.get(voxel_type).textures.tile(normal);
+        self.atlas.uv_rect(tile).face_uvs(normal, size)
+    }
+
+    /// Every defined block type, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (VoxelType, &BlockDefinition)> {
+        self.blocks.iter().filter_map(|definition| {
+            definition.as_ref().map(|definition| (VoxelType(definition.id), definition))
+        })
+    }
+}
+
+/// Keeps the block definitions alive until the registry is built.
+#[derive(Resource)]
+pub struct BlockDefinitionsHandle(pub Handle<BlockDefinitions>);
+
+/// Turns the loaded block definitions into a BlockRegistry.
+pub fn build_block_registry(
+    mut commands: Commands,
+    handle: Res<BlockDefinitionsHandle>,
+    definitions: Res<Assets<BlockDefinitions>>,
+) {
+    let Some(definitions) = definitions.get(&handle.0) else {
+        return;
+    };
+
+    match BlockRegistry::new(definitions.atlas, definitions.blocks.clone()) {
+        Ok(registry) => commands.insert_resource(registry),
+        Err(err) => error!("Could not build the block registry: {err}"),
+    }
+    commands.remove_resource::<BlockDefinitionsHandle>();
+}
@@ -0,0 +1,293 @@
+use crate::voxel::util::CHUNK_VOL;
+use crate::voxel::voxel::VoxelType;
+
+/// How the voxels of a Chunk are stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoxelStorage {
+    /// Every voxel in the chunk is the same type.
+    /// Most chunks are either all air or all stone, so this saves a lot.
+    Single(VoxelType),
+    /// The chunk has a mix of types.
+    Paletted(PalettedVoxels),
+}
+
+impl VoxelStorage {
+    /// The VoxelType at a voxel index.
+    pub fn get(&self, index: usize) -> VoxelType {
+        match self {
+            VoxelStorage::Single(voxel_type) => *voxel_type,
+            VoxelStorage::Paletted(paletted) => paletted.get(index),
+        }
+    }
+
+    /// Changes the VoxelType at a voxel index.
+    /// A single value chunk gets turned into a paletted one when needed.
+    pub fn set(&mut self, index: usize, voxel_type: VoxelType) {
+        match self {
+            VoxelStorage::Single(current) => {
+                if *current != voxel_type {
+                    let mut paletted = PalettedVoxels::filled(*current);
+                    paletted.set(index, voxel_type);
+                    *self = VoxelStorage::Paletted(paletted);
+                }
+            }
+            VoxelStorage::Paletted(paletted) => paletted.set(index, voxel_type),
+        }
+    }
+
+    /// Drops unused palette entries and shrinks the indices to match.
+    /// If only one type is left, we go back to a single value.
+    pub fn compact(&mut self) {
+        if let VoxelStorage::Paletted(paletted) = self {
+            *self = paletted.compacted();
+        }
+    }
+}
+
+/// Voxels stored as indices into a palette of the VoxelTypes the chunk uses.
+/// The indices are bit-packed into u64 words, so a chunk with only two types
+/// takes a single bit per voxel. Indices never straddle two words.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PalettedVoxels {
+    palette: Vec<VoxelType>,
+    bits: u32,
+    data: Vec<u64>,
+}
+
+impl PalettedVoxels {
+    /// A chunk where every voxel uses the first palette entry.
+    fn filled(voxel_type: VoxelType) -> Self {
+        Self::with_palette(vec![voxel_type], 1)
+    }
+
+    fn with_palette(palette: Vec<VoxelType>, bits: u32) -> Self {
+        let per_word = (u64::BITS / bits) as usize;
+        Self {
+            palette,
+            bits,
+            data: vec![0; (CHUNK_VOL as usize).div_ceil(per_word)],
+        }
+    }
+
+    fn get(&self, index: usize) -> VoxelType {
+        self.palette[self.palette_index(index)]
+    }
+
+    fn set(&mut self, index: usize, voxel_type: VoxelType) {
+        let palette_index = match self.palette.iter().position(|t| *t == voxel_type) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(voxel_type);
+                if self.palette.len() > 1 << self.bits {
+                    self.repack(self.bits + 1);
+                }
+                self.palette.len() - 1
+            }
+        };
+
+        self.set_palette_index(index, palette_index);
+    }
+
+    /// Where in `data` an index lives: the word and the shift within it.
+    fn location(&self, index: usize) -> (usize, u32) {
+        let per_word = (u64::BITS / self.bits) as usize;
+        (index / per_word, (index % per_word) as u32 * self.bits)
+    }
+
+    fn palette_index(&self, index: usize) -> usize {
+        let (word, shift) = self.location(index);
+        let mask = (1u64 << self.bits) - 1;
+        ((self.data[word] >> shift) & mask) as usize
+    }
+
+    fn set_palette_index(&mut self, index: usize, palette_index: usize) {
+        let (word, shift) = self.location(index);
+        let mask = (1u64 << self.bits) - 1;
+        self.data[word] = (self.data[word] & !(mask << shift)) | ((palette_index as u64) << shift);
+    }
+
+    /// Moves all indices over to a new bit width.
+    fn repack(&mut self, bits: u32) {
+        let mut repacked = Self::with_palette(self.palette.clone(), bits);
+        for index in 0..CHUNK_VOL as usize {
+            repacked.set_palette_index(index, self.palette_index(index));
+        }
+        *self = repacked;
+    }
+
+    fn compacted(&self) -> VoxelStorage {
+        let mut used = vec![false; self.palette.len()];
+        for index in 0..CHUNK_VOL as usize {
+            used[self.palette_index(index)] = true;
+        }
+
+        // Maps old palette indices to new ones
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (old_index, voxel_type) in self.palette.iter().enumerate() {
+            if used[old_index] {
+                remap[old_index] = palette.len();
+                palette.push(*voxel_type);
+            }
+        }
+
+        if palette.len() == 1 {
+            return VoxelStorage::Single(palette[0]);
+        }
+
+        let bits = usize::BITS - (palette.len() - 1).leading_zeros();
+        let mut compacted = Self::with_palette(palette, bits);
+        for index in 0..CHUNK_VOL as usize {
+            compacted.set_palette_index(index, remap[self.palette_index(index)]);
+        }
+
+        VoxelStorage::Paletted(compacted)
+    }
+}
+
+/// A small number for every voxel in a Chunk, from 0 to 15.
+/// Used for light levels and liquid levels.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NibbleStorage {
+    /// Every voxel has the same level. Open sky, solid ground and still water all end up like this.
+    Uniform(u8),
+    /// A level for every voxel, two to a byte. Even indices go in the low half.
+    Nibbles(Box<[u8]>),
+}
+
+impl NibbleStorage {
+    pub fn get(&self, index: usize) -> u8 {
+        match self {
+            NibbleStorage::Uniform(level) => *level,
+            NibbleStorage::Nibbles(nibbles) => (nibbles[index / 2] >> ((index % 2) * 4)) & 0xF,
+        }
+    }
+
+    /// Changes the level at a voxel index.
+    /// A uniform chunk gets a level for every voxel when needed.
+    pub fn set(&mut self, index: usize, level: u8) {
+        if let NibbleStorage::Uniform(current) = *self {
+            if current == level {
+                return;
+            }
+            *self = NibbleStorage::Nibbles(vec![current | (current << 4); CHUNK_VOL as usize / 2].into_boxed_slice());
+        }
+
+        if let NibbleStorage::Nibbles(nibbles) = self {
+            let shift = (index % 2) * 4;
+            nibbles[index / 2] = (nibbles[index / 2] & !(0xF << shift)) | ((level & 0xF) << shift);
+        }
+    }
+
+    /// Goes back to a single level if every voxel has the same one.
+    pub fn compact(&mut self) {
+        if let NibbleStorage::Nibbles(nibbles) = self {
+            let first = nibbles[0];
+            if first & 0xF == first >> 4 && nibbles.iter().all(|pair| *pair == first) {
+                *self = NibbleStorage::Uniform(first & 0xF);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voxel::util::CHUNK_VOL;
+    use crate::voxel::voxel::VoxelType;
+    use super::{NibbleStorage, VoxelStorage};
+
+    const VOL: usize = CHUNK_VOL as usize;
+
+    /// Some type for every voxel, mixed up so neighbouring voxels rarely match.
+    fn mixed_type(index: usize, types: usize) -> VoxelType {
+        VoxelType(((index * 7919) % types) as u16)
+    }
+
+    fn paletted_bits(storage: &VoxelStorage) -> u32 {
+        match storage {
+            VoxelStorage::Paletted(paletted) => paletted.bits,
+            VoxelStorage::Single(_) => panic!("expected paletted voxels"),
+        }
+    }
+
+    #[test]
+    fn repacks_as_the_palette_grows() {
+        // Just past every width up to 10 bits
+        for (types, bits) in [(2, 1), (3, 2), (5, 3), (9, 4), (17, 5), (33, 6), (65, 7), (129, 8), (257, 9), (513, 10)] {
+            let mut storage = VoxelStorage::Single(VoxelType(0));
+            for index in 0..VOL {
+                storage.set(index, mixed_type(index, types));
+            }
+
+            assert_eq!(paletted_bits(&storage), bits, "{types} types");
+            for index in 0..VOL {
+                assert_eq!(storage.get(index), mixed_type(index, types), "{types} types at {index}");
+            }
+        }
+    }
+
+    #[test]
+    fn compacting_drops_unused_types() {
+        let mut storage = VoxelStorage::Single(VoxelType(0));
+        for index in 0..VOL {
+            storage.set(index, mixed_type(index, 100));
+        }
+        // Only two of them are left
+        for index in 0..VOL {
+            storage.set(index, VoxelType(40 + (index % 2) as u16));
+        }
+
+        storage.compact();
+        assert_eq!(paletted_bits(&storage), 1);
+        for index in 0..VOL {
+            assert_eq!(storage.get(index), VoxelType(40 + (index % 2) as u16), "at {index}");
+        }
+    }
+
+    #[test]
+    fn compacting_a_single_type_goes_back_to_single() {
+        let mut storage = VoxelStorage::Single(VoxelType::AIR);
+        for index in 0..VOL {
+            storage.set(index, mixed_type(index, 20));
+        }
+        for index in 0..VOL {
+            storage.set(index, VoxelType::STONE);
+        }
+
+        storage.compact();
+        assert_eq!(storage, VoxelStorage::Single(VoxelType::STONE));
+    }
+
+    #[test]
+    fn setting_the_same_type_keeps_single() {
+        let mut storage = VoxelStorage::Single(VoxelType::STONE);
+        storage.set(10, VoxelType::STONE);
+        assert_eq!(storage, VoxelStorage::Single(VoxelType::STONE));
+    }
+
+    #[test]
+    fn nibbles_round_trip() {
+        let mut nibbles = NibbleStorage::Uniform(3);
+        nibbles.set(0, 3);
+        assert_eq!(nibbles, NibbleStorage::Uniform(3));
+
+        for index in 0..VOL {
+            nibbles.set(index, (index * 7 % 16) as u8);
+        }
+        nibbles.compact();
+        for index in 0..VOL {
+            assert_eq!(nibbles.get(index), (index * 7 % 16) as u8, "at {index}");
+        }
+
+        // Neighbours share a byte, changing one leaves the other alone
+        nibbles.set(1, 15);
+        assert_eq!(nibbles.get(0), 0);
+        assert_eq!(nibbles.get(2), 14);
+
+        for index in 0..VOL {
+            nibbles.set(index, 9);
+        }
+        nibbles.compact();
+        assert_eq!(nibbles, NibbleStorage::Uniform(9));
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use bevy::prelude::*;
+use crate::voxel::tasks::{ChunkEntities, ChunkTasks};
+use crate::voxel::util::{CHUNK_SIZE_F32, neighbour_positions};
+use crate::worldgen::pipeline::ChunkStatus;
+use crate::worldgen::world::VoxelWorld;
+
+/// Chunks get loaded around whatever entity has this.
+/// If there are several, only the first one is used.
+#[derive(Component)]
+pub struct ChunkLoader;
+
+/// Controls how much of the world is kept around the ChunkLoader.
+#[derive(Resource)]
+pub struct ChunkStreaming {
+    /// Horizontal view distance, in chunks.
+    pub view_distance: i32,
+    /// Vertical view distance, in chunks.
+    pub vertical_view_distance: i32,
+    /// The chunk the loader was in last time we checked.
+    center: Option<IVec3>,
+}
+
+impl Default for ChunkStreaming {
+    fn default() -> Self {
+        Self {
+            view_distance: 8,
+            vertical_view_distance: 4,
+            center: None,
+        }
+    }
+}
+
+impl ChunkStreaming {
+    /// Whether a chunk is within the view distance of the loader.
+    pub fn in_range(&self, chunk_pos: IVec3) -> bool {
+        let Some(center) = self.center else {
+            return false;
+        };
+
+        let offset = chunk_pos - center;
+        offset.x * offset.x + offset.z * offset.z <= self.view_distance * self.view_distance
+            && offset.y.abs() <= self.vertical_view_distance
+    }
+
+    /// The chunk the loader is in, once it's been seen.
+    pub fn center(&self) -> Option<IVec3> {
+        self.center
+    }
+
+    /// How far each chunk around the loader should be generated.
+    ///
+    /// Chunks in range get finished. A stage can't run until all neighbours
+    /// are done with the one before it, so every ring of chunks around those
+    /// only has to go one stage less far than the ring inside of it.
+    pub fn targets(&self) -> HashMap<IVec3, ChunkStatus> {
+        let mut targets = HashMap::new();
+        let Some(center) = self.center else {
+            return targets;
+        };
+
+        let mut ring = Vec::new();
+        for x in -self.view_distance..=self.view_distance {
+            for y in -self.vertical_view_distance..=self.vertical_view_distance {
+                for z in -self.view_distance..=self.view_distance {
+                    let chunk_pos = center + IVec3::new(x, y, z);
+                    if self.in_range(chunk_pos) {
+                        targets.insert(chunk_pos, ChunkStatus::FINISHED);
+                        ring.push(chunk_pos);
+                    }
+                }
+            }
+        }
+
+        // Terrain doesn't need anything from its neighbours, so the rings stop there
+        let mut status = ChunkStatus::FINISHED;
+        while let Some(previous) = status.previous().filter(|_| status > ChunkStatus::Terrain) {
+            status = previous;
+
+            let mut next_ring = Vec::new();
+            for chunk_pos in ring {
+                for neighbour_pos in neighbour_positions(chunk_pos) {
+                    if let Entry::Vacant(entry) = targets.entry(neighbour_pos) {
+                        entry.insert(status);
+                        next_ring.push(neighbour_pos);
+                    }
+                }
+            }
+            ring = next_ring;
+        }
+
+        targets
+    }
+}
+
+/// Works out how far every chunk around the loader should be generated,
+/// queues the ones that are missing, nearest first, and throws away the ones
+/// that aren't needed anymore. Meshes are only kept for chunks in view.
+/// Only does anything once the loader moves into another chunk.
+pub fn stream_chunks(
+    mut commands: Commands,
+    loader_query: Query<&Transform, With<ChunkLoader>>,
+    mut streaming: ResMut<ChunkStreaming>,
+    mut voxel_world: ResMut<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+) {
+    let Some(transform) = loader_query.iter().next() else {
+        return;
+    };
+
+    let center = (transform.translation / CHUNK_SIZE_F32).floor().as_ivec3();
+    if streaming.center == Some(center) {
+        return;
+    }
+    streaming.center = Some(center);
+    chunk_tasks.targets = streaming.targets();
+
+    // Unload everything that's no longer needed, saving any changes first.
+    // Chunks a stage is busy with get unloaded once it's done.
+    let unneeded: Vec<IVec3> = voxel_world.chunks.keys()
+        .chain(voxel_world.proto_chunks.keys())
+        .copied()
+        .filter(|chunk_pos| !chunk_tasks.targets.contains_key(chunk_pos) && !chunk_tasks.busy.contains(chunk_pos))
+        .collect();
+    voxel_world.unload_chunks(&unneeded);
+
+    chunk_entities.meshes.retain(|chunk_pos, entities| {
+        let keep = streaming.in_range(*chunk_pos);
+        if !keep {
+            for entity in entities.iter() {
+                commands.entity(*entity).despawn();
+            }
+        }
+        keep
+    });
+
+    let ChunkTasks { generate_queue, pending, targets, .. } = &mut *chunk_tasks;
+    generate_queue.retain(|chunk_pos| {
+        let keep = targets.contains_key(chunk_pos);
+        if !keep {
+            pending.remove(chunk_pos);
+        }
+        keep
+    });
+
+    // Queue up everything that's missing, and mesh the finished
+    // chunks that were kept around and came back into view
+    let targets: Vec<IVec3> = chunk_tasks.targets.keys().copied().collect();
+    for chunk_pos in targets {
+        if voxel_world.chunk_status(chunk_pos).is_none() {
+            chunk_tasks.queue_generation(chunk_pos);
+        } else if streaming.in_range(chunk_pos)
+            && voxel_world.chunks.contains_key(&chunk_pos)
+            && !chunk_entities.meshes.contains_key(&chunk_pos)
+        {
+            chunk_tasks.queue_mesh(chunk_pos);
+        }
+    }
+
+    chunk_tasks.generate_queue
+        .make_contiguous()
+        .sort_by_key(|chunk_pos| (*chunk_pos - center).length_squared());
+}
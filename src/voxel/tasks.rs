@@ -0,0 +1,567 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use bevy::pbr::{NotShadowCaster, NotShadowReceiver};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use crate::global::{MeshingMode, Settings};
+use crate::voxel::chunk::{Chunk, ChunkMap};
+use crate::voxel::chunk_mesh::ChunkMesh;
+use crate::voxel::light::{light_chunk, open_to_sky, update_light};
+use crate::voxel::plugin::ChunkMaterials;
+use crate::voxel::registry::BlockRegistry;
+use crate::voxel::streaming::ChunkStreaming;
+use crate::voxel::util::neighbour_positions;
+use crate::voxel::water::{ATTRIBUTE_LIQUID_DEPTH, ATTRIBUTE_LIQUID_FLOW, LiquidMesh};
+use crate::worldgen::generator::ChunkGenerator;
+use crate::worldgen::pipeline::{ChunkNeighbourhood, ChunkStatus, ProtoChunk, StageInput, StageOutput};
+use crate::worldgen::world::{ColumnMap, VoxelWorld};
+
+/// Keeps track of the chunks that are waiting for, or going through,
+/// generation and meshing on the AsyncComputeTaskPool.
+#[derive(Resource)]
+pub struct ChunkTasks {
+    /// Chunks waiting to be loaded from disk or started from scratch, nearest first.
+    pub generate_queue: VecDeque<IVec3>,
+    /// Every chunk that is queued or currently being loaded.
+    pub pending: HashSet<IVec3>,
+    /// How far along the generation stages each chunk should go.
+    /// Chunks without a target get unloaded.
+    pub targets: HashMap<IVec3, ChunkStatus>,
+    /// Chunks that a stage task is working on, or reading and writing.
+    /// Nothing else may run on them until it's done.
+    pub busy: HashSet<IVec3>,
+    /// Generated chunks waiting to be meshed.
+    pub mesh_queue: Vec<IVec3>,
+    /// The id of the newest meshing task started for each chunk.
+    /// Results of older tasks are thrown away, so a slow task
+    /// can't overwrite the mesh of a newer edit.
+    latest_mesh_task: HashMap<IVec3, u64>,
+    next_mesh_task: u64,
+    /// How many new tasks of each kind we start in a single frame.
+    pub tasks_per_frame: usize,
+    /// How many finished tasks of each kind we pick up in a single frame.
+    /// Keeps big batches of results from causing hitches.
+    pub results_per_frame: usize,
+}
+
+impl Default for ChunkTasks {
+    fn default() -> Self {
+        Self {
+            generate_queue: VecDeque::new(),
+            pending: HashSet::new(),
+            targets: HashMap::new(),
+            busy: HashSet::new(),
+            mesh_queue: Vec::new(),
+            latest_mesh_task: HashMap::new(),
+            next_mesh_task: 0,
+            tasks_per_frame: 64,
+            results_per_frame: 16,
+        }
+    }
+}
+
+impl ChunkTasks {
+    /// Queues a chunk for generation, unless it's already on its way.
+    pub fn queue_generation(&mut self, chunk_pos: IVec3) {
+        if self.pending.insert(chunk_pos) {
+            self.generate_queue.push_back(chunk_pos);
+        }
+    }
+
+    /// Queues a chunk for (re)meshing, unless it's already waiting.
+    pub fn queue_mesh(&mut self, chunk_pos: IVec3) {
+        if !self.mesh_queue.contains(&chunk_pos) {
+            self.mesh_queue.push(chunk_pos);
+        }
+    }
+
+    /// Queues a chunk for remeshing ahead of everything else.
+    /// Used for edits, which should show up as soon as possible.
+    pub fn queue_remesh(&mut self, chunk_pos: IVec3) {
+        self.mesh_queue.retain(|queued_pos| *queued_pos != chunk_pos);
+        self.mesh_queue.insert(0, chunk_pos);
+    }
+
+    /// Whether a chunk is supposed to be finished, but isn't yet.
+    /// Meshing its neighbours has to wait for it.
+    fn is_unfinished(&self, voxel_world: &VoxelWorld, chunk_pos: IVec3) -> bool {
+        self.targets.get(&chunk_pos) == Some(&ChunkStatus::FINISHED) && !voxel_world.chunks.contains_key(&chunk_pos)
+    }
+}
+
+/// The mesh entities spawned for each chunk.
+/// A chunk that has been meshed always has an entry, even if it had nothing to draw.
+#[derive(Resource, Default)]
+pub struct ChunkEntities {
+    pub meshes: HashMap<IVec3, Vec<Entity>>,
+}
+
+#[derive(Component)]
+pub struct LoadChunkTask {
+    position: IVec3,
+    task: Task<ProtoChunk>,
+}
+
+#[derive(Component)]
+pub struct StageChunkTask {
+    /// The chunks marked busy for this task.
+    locked: Vec<IVec3>,
+    task: Task<StageOutput>,
+}
+
+#[derive(Component)]
+pub struct MeshChunkTask {
+    position: IVec3,
+    id: u64,
+    task: Task<BuiltChunkMesh>,
+}
+
+/// The finished Bevy meshes for a chunk. Empty meshes are left out.
+struct BuiltChunkMesh {
+    mesh: Option<Mesh>,
+    liquid_mesh: Option<Mesh>,
+    glowing_liquid_mesh: Option<Mesh>,
+    cutout_mesh: Option<Mesh>,
+}
+
+/// Starts loading queued chunks from disk.
+/// Chunks that were never saved start out empty instead.
+/// Light isn't saved, so finished chunks get lit again.
+pub fn queue_load_tasks(
+    mut commands: Commands,
+    voxel_world: Res<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    blocks: Res<BlockRegistry>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for _ in 0..chunk_tasks.tasks_per_frame {
+        let Some(chunk_pos) = chunk_tasks.generate_queue.pop_front() else {
+            break;
+        };
+
+        let region_storage = voxel_world.region_storage.clone();
+        let generator = voxel_world.generator.clone();
+        let blocks = blocks.clone();
+        let task = task_pool.spawn(async move {
+            let mut proto = VoxelWorld::load_or_create_chunk(&region_storage, chunk_pos);
+            if proto.status == ChunkStatus::FINISHED {
+                light_new_chunk(&mut proto.chunk, None, generator.as_ref(), &blocks);
+            }
+            proto
+        });
+        commands.spawn(LoadChunkTask { position: chunk_pos, task });
+    }
+}
+
+/// Adds loaded chunks to the world. Finished ones go straight
+/// to meshing, the rest still have stages to go through.
+pub fn poll_load_tasks(
+    mut commands: Commands,
+    mut voxel_world: ResMut<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    chunk_entities: Res<ChunkEntities>,
+    streaming: Res<ChunkStreaming>,
+    blocks: Res<BlockRegistry>,
+    mut query: Query<(Entity, &mut LoadChunkTask)>,
+) {
+    let mut finished = 0;
+    for (entity, mut load_task) in query.iter_mut() {
+        if finished >= chunk_tasks.results_per_frame {
+            break;
+        }
+
+        if let Some(proto) = block_on(future::poll_once(&mut load_task.task)) {
+            let chunk_pos = load_task.position;
+            chunk_tasks.pending.remove(&chunk_pos);
+            commands.entity(entity).despawn();
+            finished += 1;
+
+            // The loader moved away while this was loading
+            if !chunk_tasks.targets.contains_key(&chunk_pos) {
+                continue;
+            }
+
+            if proto.status == ChunkStatus::FINISHED {
+                finish_chunk(&mut voxel_world, &mut chunk_tasks, &chunk_entities, &streaming, &blocks, chunk_pos, proto.chunk);
+            } else {
+                voxel_world.proto_chunks.insert(chunk_pos, proto);
+            }
+        }
+    }
+}
+
+/// Starts the next stage for every chunk that isn't as far along as it should be,
+/// as long as its neighbours have reached the stage before. Stages that touch the
+/// neighbours also wait for them to be free. Chunks closest to the loader go first.
+pub fn queue_stage_tasks(
+    mut commands: Commands,
+    voxel_world: Res<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    streaming: Res<ChunkStreaming>,
+    blocks: Res<BlockRegistry>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+    let center = streaming.center().unwrap_or(IVec3::ZERO);
+
+    let mut behind: Vec<IVec3> = voxel_world.proto_chunks.iter()
+        .filter(|(chunk_pos, proto)| {
+            chunk_tasks.targets.get(*chunk_pos).is_some_and(|target| proto.status < *target)
+        })
+        .map(|(chunk_pos, _)| *chunk_pos)
+        .collect();
+    behind.sort_by_key(|chunk_pos| (*chunk_pos - center).length_squared());
+
+    let mut started = 0;
+    for chunk_pos in behind {
+        if started >= chunk_tasks.tasks_per_frame {
+            break;
+        }
+
+        // Might have been locked by a task started earlier this frame
+        if chunk_tasks.busy.contains(&chunk_pos) {
+            continue;
+        }
+
+        let proto = &voxel_world.proto_chunks[&chunk_pos];
+        let Some(stage) = proto.status.next() else {
+            continue;
+        };
+
+        // The first stage doesn't need anything from its neighbours
+        let neighbours_ready = proto.status == ChunkStatus::Empty || neighbour_positions(chunk_pos).all(|pos| {
+            voxel_world.chunk_status(pos).is_some_and(|status| status >= proto.status)
+                && !(stage.touches_neighbours() && chunk_tasks.busy.contains(&pos))
+        });
+        if !neighbours_ready {
+            continue;
+        }
+
+        let mut locked = vec![chunk_pos];
+        let mut neighbourhood = None;
+        if stage.touches_neighbours() {
+            let mut chunks = ChunkNeighbourhood::new(chunk_pos);
+            for pos in neighbour_positions(chunk_pos) {
+                if let Some(chunk) = voxel_world.chunks.get(&pos) {
+                    chunks.insert(chunk.clone(), false);
+                } else if let Some(neighbour) = voxel_world.proto_chunks.get(&pos) {
+                    chunks.insert(neighbour.chunk.clone(), true);
+                    if pos != chunk_pos {
+                        locked.push(pos);
+                    }
+                }
+            }
+            neighbourhood = Some(chunks);
+        }
+
+        for pos in locked.iter() {
+            chunk_tasks.busy.insert(*pos);
+        }
+
+        let generator = voxel_world.generator.clone();
+        let blocks = blocks.clone();
+        let input = StageInput { chunk: proto.clone(), neighbourhood };
+        let task = task_pool.spawn(async move {
+            let mut output = generator.run_stage(input);
+            if output.status == ChunkStatus::Lighting {
+                light_new_chunk(&mut output.chunk, output.columns.as_deref(), generator.as_ref(), &blocks);
+            }
+            output
+        });
+        commands.spawn(StageChunkTask { locked, task });
+
+        started += 1;
+    }
+}
+
+/// Stores the results of finished stages. Chunks that made it through the
+/// last stage are moved into the world and queued for meshing.
+pub fn poll_stage_tasks(
+    mut commands: Commands,
+    mut voxel_world: ResMut<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    chunk_entities: Res<ChunkEntities>,
+    streaming: Res<ChunkStreaming>,
+    blocks: Res<BlockRegistry>,
+    mut query: Query<(Entity, &mut StageChunkTask)>,
+) {
+    let mut finished = 0;
+    for (entity, mut stage_task) in query.iter_mut() {
+        if finished >= chunk_tasks.results_per_frame {
+            break;
+        }
+
+        let Some(output) = block_on(future::poll_once(&mut stage_task.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        finished += 1;
+
+        for pos in stage_task.locked.iter() {
+            chunk_tasks.busy.remove(pos);
+        }
+
+        // Generating these again wouldn't bring back what the features
+        // wrote into them, so they have to be saved from now on.
+        for neighbour in output.neighbours {
+            let neighbour_pos = neighbour.position;
+            if let Some(proto) = voxel_world.proto_chunks.get_mut(&neighbour_pos) {
+                proto.chunk = neighbour;
+                voxel_world.unsaved.insert(neighbour_pos);
+            }
+        }
+
+        let chunk_pos = output.position;
+        if let Some(proto) = voxel_world.proto_chunks.get_mut(&chunk_pos) {
+            proto.chunk = output.chunk;
+            proto.status = output.status;
+            proto.columns = output.columns;
+
+            if proto.status == ChunkStatus::FINISHED {
+                let proto = voxel_world.proto_chunks.remove(&chunk_pos).unwrap();
+                finish_chunk(&mut voxel_world, &mut chunk_tasks, &chunk_entities, &streaming, &blocks, chunk_pos, proto.chunk);
+            }
+        }
+
+        // The loader moved away while this was running
+        let unneeded: Vec<IVec3> = stage_task.locked.iter()
+            .copied()
+            .filter(|pos| !chunk_tasks.targets.contains_key(pos) && !chunk_tasks.busy.contains(pos))
+            .collect();
+        voxel_world.unload_chunks(&unneeded);
+    }
+}
+
+/// Lights a chunk on its own, just before it goes into the world.
+/// `LightUpdate::chunk_added` then connects its light with the neighbours.
+fn light_new_chunk(
+    chunk: &mut Arc<Chunk>,
+    columns: Option<&ColumnMap>,
+    generator: &dyn ChunkGenerator,
+    blocks: &BlockRegistry,
+) {
+    let chunk_pos = chunk.position;
+    light_chunk(Arc::make_mut(chunk), blocks, |x, z| open_to_sky(generator, columns, chunk_pos, x, z));
+}
+
+/// Moves a chunk that made it through every stage into the world, and meshes it if it's in view.
+/// Already meshed neighbours get remeshed, since they drew faces against the chunk
+/// while it was still missing, and so do meshed chunks its light reached.
+fn finish_chunk(
+    voxel_world: &mut VoxelWorld,
+    chunk_tasks: &mut ChunkTasks,
+    chunk_entities: &ChunkEntities,
+    streaming: &ChunkStreaming,
+    blocks: &BlockRegistry,
+    chunk_pos: IVec3,
+    chunk: Arc<Chunk>,
+) {
+    voxel_world.chunks.insert(chunk_pos, chunk);
+    if streaming.in_range(chunk_pos) {
+        chunk_tasks.queue_mesh(chunk_pos);
+    }
+
+    let lit = update_light(&mut voxel_world.chunks, blocks, |update| update.chunk_added(chunk_pos));
+
+    for neighbour_pos in neighbour_positions(chunk_pos).chain(lit) {
+        if neighbour_pos != chunk_pos && chunk_entities.meshes.contains_key(&neighbour_pos) {
+            chunk_tasks.queue_mesh(neighbour_pos);
+        }
+    }
+}
+
+/// Starts meshing tasks for chunks whose neighbours are done generating.
+/// Each task gets its own small ChunkMap with the chunk and its neighbours,
+/// which is cheap since the chunks themselves are shared.
+pub fn queue_mesh_tasks(
+    mut commands: Commands,
+    voxel_world: Res<VoxelWorld>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    blocks: Res<BlockRegistry>,
+    settings: Res<Settings>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+    let mut started = 0;
+    let mut mesh_queue = std::mem::take(&mut chunk_tasks.mesh_queue);
+
+    mesh_queue.retain(|chunk_pos| {
+        if started >= chunk_tasks.tasks_per_frame
+            || neighbour_positions(*chunk_pos).any(|pos| chunk_tasks.is_unfinished(&voxel_world, pos))
+        {
+            return true;
+        }
+
+        let Some(chunk) = voxel_world.chunks.get(chunk_pos).cloned() else {
+            // Chunk is gone, nothing to mesh.
+            return false;
+        };
+
+        let mut neighbours = ChunkMap::new();
+        for pos in neighbour_positions(*chunk_pos) {
+            if let Some(neighbour) = voxel_world.chunks.get(&pos) {
+                neighbours.insert(pos, neighbour.clone());
+            }
+        }
+
+        let blocks = blocks.clone();
+        let settings = settings.clone();
+        let task = task_pool.spawn(async move {
+            let mut chunk_mesh = ChunkMesh::default();
+            chunk_mesh.build_chunk_mesh(&chunk, &neighbours, &blocks, &settings);
+
+            BuiltChunkMesh {
+                mesh: (!chunk_mesh.mesh.vertices.is_empty())
+                    .then(|| setup_bevy_mesh(chunk_mesh.mesh, false)),
+                liquid_mesh: (!chunk_mesh.liquid_mesh.vertices.is_empty())
+                    .then(|| setup_bevy_mesh(chunk_mesh.liquid_mesh, true)),
+                glowing_liquid_mesh: (!chunk_mesh.glowing_liquid_mesh.vertices.is_empty())
+                    .then(|| setup_bevy_mesh(chunk_mesh.glowing_liquid_mesh, false)),
+                cutout_mesh: (!chunk_mesh.cutout_mesh.vertices.is_empty())
+                    .then(|| setup_bevy_mesh(chunk_mesh.cutout_mesh, false)),
+            }
+        });
+        let id = chunk_tasks.next_mesh_task;
+        chunk_tasks.next_mesh_task += 1;
+        chunk_tasks.latest_mesh_task.insert(*chunk_pos, id);
+        commands.spawn(MeshChunkTask { position: *chunk_pos, id, task });
+
+        started += 1;
+        false
+    });
+
+    // Edits might have queued more while we were busy, those go first
+    mesh_queue.append(&mut chunk_tasks.mesh_queue);
+    chunk_tasks.mesh_queue = mesh_queue;
+}
+
+/// Remeshes every chunk that has been meshed when `Settings::meshing_mode` changes.
+pub fn remesh_on_meshing_mode(
+    settings: Res<Settings>,
+    chunk_entities: Res<ChunkEntities>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    mut meshing_mode: Local<Option<MeshingMode>>,
+) {
+    // Nothing has been meshed the first time around
+    let previous = meshing_mode.replace(settings.meshing_mode);
+    if previous.is_none() || previous == Some(settings.meshing_mode) {
+        return;
+    }
+
+    for chunk_pos in chunk_entities.meshes.keys() {
+        chunk_tasks.queue_mesh(*chunk_pos);
+    }
+}
+
+/// Spawns the meshes of finished meshing tasks, within the per-frame budget.
+/// Replaces whatever meshes the chunk had before.
+#[allow(clippy::too_many_arguments)]
+pub fn poll_mesh_tasks(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut chunk_tasks: ResMut<ChunkTasks>,
+    voxel_world: Res<VoxelWorld>,
+    streaming: Res<ChunkStreaming>,
+    mut chunk_entities: ResMut<ChunkEntities>,
+    settings: Res<Settings>,
+    mut query: Query<(Entity, &mut MeshChunkTask)>,
+) {
+    let mut finished = 0;
+    for (entity, mut mesh_task) in query.iter_mut() {
+        if finished >= chunk_tasks.results_per_frame {
+            break;
+        }
+
+        if let Some(built_mesh) = block_on(future::poll_once(&mut mesh_task.task)) {
+            commands.entity(entity).despawn();
+            finished += 1;
+
+            // A newer task is meshing this chunk
+            let chunk_pos = mesh_task.position;
+            if chunk_tasks.latest_mesh_task.get(&chunk_pos) != Some(&mesh_task.id) {
+                continue;
+            }
+            chunk_tasks.latest_mesh_task.remove(&chunk_pos);
+
+            // Chunk got unloaded, or went out of view, while this was meshing
+            if !voxel_world.chunks.contains_key(&chunk_pos) || !streaming.in_range(chunk_pos) {
+                continue;
+            }
+
+            let mut entities = Vec::new();
+            if let Some(mesh) = built_mesh.mesh {
+                entities.push(commands.spawn(MaterialMeshBundle {
+                    mesh: meshes.add(mesh),
+                    material: chunk_materials.solid.clone(),
+                    ..default()
+                }).id());
+            }
+
+            if let Some(liquid_mesh) = built_mesh.liquid_mesh {
+                let mesh = meshes.add(liquid_mesh);
+                let mut liquid = if settings.water_shader {
+                    commands.spawn(MaterialMeshBundle {
+                        mesh,
+                        material: chunk_materials.water.clone(),
+                        ..default()
+                    })
+                } else {
+                    commands.spawn(MaterialMeshBundle {
+                        mesh,
+                        material: chunk_materials.liquid.clone(),
+                        ..default()
+                    })
+                };
+                entities.push(liquid.insert((LiquidMesh, NotShadowReceiver, NotShadowCaster)).id());
+            }
+
+            // Never gets the water shader, so it isn't a LiquidMesh
+            if let Some(glowing_liquid_mesh) = built_mesh.glowing_liquid_mesh {
+                entities.push(commands.spawn((
+                    MaterialMeshBundle {
+                        mesh: meshes.add(glowing_liquid_mesh),
+                        material: chunk_materials.liquid.clone(),
+                        ..default()
+                    },
+                    NotShadowReceiver,
+                    NotShadowCaster,
+                )).id());
+            }
+
+            if let Some(cutout_mesh) = built_mesh.cutout_mesh {
+                entities.push(commands.spawn(MaterialMeshBundle {
+                    mesh: meshes.add(cutout_mesh),
+                    material: chunk_materials.cutout.clone(),
+                    ..default()
+                }).id());
+            }
+
+            if let Some(old_entities) = chunk_entities.meshes.insert(chunk_pos, entities) {
+                for old_entity in old_entities {
+                    commands.entity(old_entity).despawn();
+                }
+            }
+        }
+    }
+}
+
+fn setup_bevy_mesh(voxel_mesh: crate::voxel::mesh::Mesh, liquid: bool) -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, voxel_mesh.vertices.clone());
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, voxel_mesh.normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, voxel_mesh.uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, voxel_mesh.tiles);
+    // AO and light, liquids included
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, voxel_mesh.colors);
+    if liquid {
+        mesh.insert_attribute(ATTRIBUTE_LIQUID_FLOW, voxel_mesh.flows);
+        mesh.insert_attribute(ATTRIBUTE_LIQUID_DEPTH, voxel_mesh.depths);
+    }
+
+    mesh.insert_indices(Indices::U32(voxel_mesh.indices));
+
+    mesh
+}
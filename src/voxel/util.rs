@@ -1,12 +1,11 @@
-use std::collections::HashMap;
 use bevy::math::IVec3;
-use crate::voxel::chunk::Chunk;
+use crate::voxel::chunk::{Chunk, ChunkMap};
+use crate::voxel::light::{LightLevels, VertexLight};
+use crate::voxel::registry::BlockRegistry;
 use crate::voxel::voxel::VoxelType;
 
 pub const CHUNK_SIZE: i32 = 32;
-#[allow(dead_code)]
 pub const CHUNK_SIZE_F32: f32 = CHUNK_SIZE as f32;
-pub const CHUNK_SIZE_F64: f64 = CHUNK_SIZE as f64;
 pub const CHUNK_AREA: i32 = CHUNK_SIZE * CHUNK_SIZE;
 pub const CHUNK_VOL: i32 = CHUNK_AREA * CHUNK_SIZE;
 
@@ -16,50 +15,121 @@ pub fn voxel_index(x: i32, y: i32, z: i32) -> usize {
     (x + CHUNK_SIZE * z + CHUNK_AREA * y) as usize
 }
 
+/// The position of the chunk a world position is inside of.
+pub fn chunk_position(world_pos: IVec3) -> IVec3 {
+    world_pos.div_euclid(IVec3::splat(CHUNK_SIZE))
+}
+
+/// A world position turned into a position inside its chunk.
+pub fn local_position(world_pos: IVec3) -> IVec3 {
+    world_pos.rem_euclid(IVec3::splat(CHUNK_SIZE))
+}
+
+/// The position itself and all 26 positions surrounding it.
+pub fn neighbour_positions(chunk_pos: IVec3) -> impl Iterator<Item = IVec3> {
+    (-1..=1).flat_map(move |x| {
+        (-1..=1).flat_map(move |y| {
+            (-1..=1).map(move |z| chunk_pos + IVec3::new(x, y, z))
+        })
+    })
+}
+
+/// The chunk a world position is in, plus the neighbours that touch
+/// the position when it sits on the border. Those look at it for culling,
+/// AO and light, so their meshes depend on it too.
+pub fn chunks_touching(world_pos: IVec3) -> Vec<IVec3> {
+    let chunk_pos = chunk_position(world_pos);
+    let local_pos = local_position(world_pos);
+    let border_offsets = |local: i32| -> &'static [i32] {
+        match local {
+            0 => &[0, -1],
+            local if local == CHUNK_SIZE - 1 => &[0, 1],
+            _ => &[0],
+        }
+    };
+
+    let mut chunks = Vec::new();
+    for x in border_offsets(local_pos.x) {
+        for y in border_offsets(local_pos.y) {
+            for z in border_offsets(local_pos.z) {
+                chunks.push(chunk_pos + IVec3::new(*x, *y, *z));
+            }
+        }
+    }
+
+    chunks
+}
+
+/// The 8 positions around `origin_pos` in a plane, going around it in the order
+/// `side_ao` and `side_light` expect.
+fn plane_neighbours(origin_pos: IVec3, plane: IVec3) -> [IVec3; 8] {
+    let IVec3 { x, y, z} = origin_pos;
+
+    match plane {
+        IVec3::X => [
+            IVec3::new(x, y, z - 1),
+            IVec3::new(x, y - 1, z - 1),
+            IVec3::new(x, y - 1, z),
+            IVec3::new(x, y - 1, z + 1),
+            IVec3::new(x, y, z + 1),
+            IVec3::new(x, y + 1, z + 1),
+            IVec3::new(x, y + 1, z),
+            IVec3::new(x, y + 1, z - 1),
+        ],
+        IVec3::Y => [
+            IVec3::new(x, y, z - 1),
+            IVec3::new(x - 1, y, z - 1),
+            IVec3::new(x - 1, y, z),
+            IVec3::new(x - 1, y, z + 1),
+            IVec3::new(x, y, z + 1),
+            IVec3::new(x + 1, y, z + 1),
+            IVec3::new(x + 1, y, z),
+            IVec3::new(x + 1, y, z - 1),
+        ],
+        IVec3::Z => [
+            IVec3::new(x - 1, y, z),
+            IVec3::new(x - 1, y - 1, z),
+            IVec3::new(x, y - 1, z),
+            IVec3::new(x + 1, y - 1, z),
+            IVec3::new(x + 1, y, z),
+            IVec3::new(x + 1, y + 1, z),
+            IVec3::new(x, y + 1, z),
+            IVec3::new(x - 1, y + 1, z),
+        ],
+        _ => unreachable!()
+    }
+}
+
 /// Ambient Occlusion.
 /// We have to check every voxel surrounding the origin position.
 pub fn get_ao(
     chunk: &Chunk,
+    blocks: &BlockRegistry,
     current_voxel_type: &VoxelType,
     origin_pos: IVec3,
     plane: IVec3,
-    world_chunks: &HashMap<IVec3, Chunk>
+    world_chunks: &ChunkMap
 ) -> [u32; 4] {
-    let IVec3 { x, y, z} = origin_pos;
+    side_ao(plane_neighbours(origin_pos, plane)
+        .map(|pos| !chunk.is_void(blocks, current_voxel_type, pos, world_chunks)))
+}
 
-    match plane {
-        IVec3::X => side_ao([
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y, z - 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y - 1, z - 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y - 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y - 1, z + 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y, z + 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y + 1, z + 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y + 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y + 1, z - 1), world_chunks)
-        ]),
-        IVec3::Y => side_ao([
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y, z - 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x - 1, y, z - 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x - 1, y, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x - 1, y, z + 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y, z + 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x + 1, y, z + 1), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x + 1, y, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x + 1, y, z - 1), world_chunks)
-        ]),
-        IVec3::Z => side_ao([
-            !chunk.is_void(current_voxel_type, IVec3::new(x - 1, y, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x - 1, y - 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y - 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x + 1, y - 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x + 1, y, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x + 1, y + 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x, y + 1, z), world_chunks),
-            !chunk.is_void(current_voxel_type, IVec3::new(x - 1, y + 1, z), world_chunks)
-        ]),
-        _ => unreachable!()
-    }
+/// Smooth lighting. Every corner of a face gets the light of the voxel
+/// in front of it averaged with the voxels around that corner, the same ones AO looks at.
+pub fn get_light(
+    chunk: &Chunk,
+    blocks: &BlockRegistry,
+    current_voxel_type: &VoxelType,
+    origin_pos: IVec3,
+    plane: IVec3,
+    world_chunks: &ChunkMap
+) -> [VertexLight; 4] {
+    let neighbours = plane_neighbours(origin_pos, plane).map(|pos| {
+        chunk.is_void(blocks, current_voxel_type, pos, world_chunks)
+            .then(|| chunk.light_at(pos, world_chunks))
+    });
+
+    side_light(chunk.light_at(origin_pos, world_chunks), neighbours)
 }
 
 /// Checks the 4 relevant corners in order to get the right AO.
@@ -74,6 +144,32 @@ fn side_ao(neighbours: [bool; 8]) -> [u32; 4] {
     ]
 }
 
+/// Averages the light around each of the 4 corners, in the same order as `side_ao`.
+/// Solid neighbours are `None`, they have no light to give and are left out.
+fn side_light(origin: LightLevels, neighbours: [Option<LightLevels>; 8]) -> [VertexLight; 4] {
+    let corner = |side1: usize, corner: usize, side2: usize| -> VertexLight {
+        // Light can't squeeze through between two solid sides, just like AO
+        let corner = if neighbours[side1].is_none() && neighbours[side2].is_none() {
+            None
+        } else {
+            neighbours[corner]
+        };
+
+        let samples = [Some(origin), neighbours[side1], corner, neighbours[side2]];
+        let count = samples.iter().flatten().count() as f32;
+        std::array::from_fn(|channel| {
+            samples.iter().flatten().map(|light| light[channel] as f32).sum::<f32>() / count
+        })
+    };
+
+    [
+        corner(0, 1, 2),
+        corner(6, 7, 0),
+        corner(4, 5, 6),
+        corner(2, 3, 4),
+    ]
+}
+
 /// Retrieves the AO value.
 fn ao_value(side1: bool, corner: bool, side2: bool) -> u32 {
     match (side1, corner, side2) {
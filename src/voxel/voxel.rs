@@ -1,58 +1,42 @@
-use bevy::prelude::*;
+use serde::Deserialize;
 
-#[derive(Debug, Clone)]
-#[derive(PartialEq)]
-pub enum VoxelType {
-    AIR,
-    GRASS,
-    STONE,
-    WATER,
-}
+/// The id of a block type. What an id looks and behaves like is
+/// decided by the BlockRegistry, but world generation relies on
+/// a few built-in ones that every block file has to define.
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct VoxelType(pub u16);
 
 impl VoxelType {
-    /// If something is AIR, it's "invisible".
-    pub fn is_visible(&self) -> bool {
-        *self != VoxelType::AIR
-    }
-
-    pub fn is_liquid(&self) -> bool {
-        *self == VoxelType::WATER
-    }
-
-    /// Some blocks are technically "visible"
-    /// but should still have things render behind them.
-    /// Things like WATER, etc.
-    pub fn should_render(&self) -> bool {
-        match *self {
-            VoxelType::AIR => true,
-            VoxelType::WATER => true,
-            _ => false
-        }
-    }
+    pub const AIR: VoxelType = VoxelType(0);
+    pub const GRASS: VoxelType = VoxelType(1);
+    pub const STONE: VoxelType = VoxelType(2);
+    pub const WATER: VoxelType = VoxelType(3);
+    pub const DIRT: VoxelType = VoxelType(4);
+    pub const SAND: VoxelType = VoxelType(5);
+    pub const SNOW: VoxelType = VoxelType(6);
+    pub const WOOD: VoxelType = VoxelType(7);
+    pub const LEAVES: VoxelType = VoxelType(8);
+    pub const TALL_GRASS: VoxelType = VoxelType(9);
+    pub const COAL_ORE: VoxelType = VoxelType(10);
+    pub const IRON_ORE: VoxelType = VoxelType(11);
+    pub const GOLD_ORE: VoxelType = VoxelType(12);
 
-    pub fn type_to_uvs(&self) -> Vec<[f32; 2]> {
-        match *self {
-            VoxelType::GRASS => vec![[0.0, 0.0], [0.0, 0.0], [0.0, 0.0], [0.0, 0.0]],
-            VoxelType::STONE => vec![[0.1, 0.0], [0.1, 0.0], [0.1, 0.0], [0.1, 0.0]],
-            VoxelType::WATER => vec![[0.2, 0.0], [0.2, 0.0], [0.2, 0.0], [0.2, 0.0]],
-            _ => unreachable!()
-        }
-    }
+    /// The ids the world generator can't do without.
+    pub const BUILT_IN: [VoxelType; 13] = [
+        VoxelType::AIR,
+        VoxelType::GRASS,
+        VoxelType::STONE,
+        VoxelType::WATER,
+        VoxelType::DIRT,
+        VoxelType::SAND,
+        VoxelType::SNOW,
+        VoxelType::WOOD,
+        VoxelType::LEAVES,
+        VoxelType::TALL_GRASS,
+        VoxelType::COAL_ORE,
+        VoxelType::IRON_ORE,
+        VoxelType::GOLD_ORE,
+    ];
 }
-
-#[derive(Component, Debug, Clone)]
-pub struct Voxel {
-    pub local_position: IVec3,
-    pub world_position: IVec3,
-    pub voxel_type: VoxelType,
-}
-
-impl Default for Voxel {
-    fn default() -> Self {
-        Self {
-            local_position: IVec3::ZERO,
-            world_position: IVec3::ZERO,
-            voxel_type: VoxelType::AIR,
-        }
-    }
-}
\ No newline at end of file
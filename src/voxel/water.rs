@@ -0,0 +1,141 @@
+use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
+use bevy::prelude::*;
+use bevy::render::mesh::{MeshVertexAttribute, MeshVertexBufferLayout};
+use bevy::render::render_resource::{
+    AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, VertexFormat,
+};
+use crate::global::Settings;
+use crate::voxel::block_material::BlockMaterial;
+use crate::voxel::plugin::ChunkMaterials;
+use crate::voxel::registry::BlockRegistry;
+
+/// Which way the liquid at a vertex flows, in world space. Longer is faster, zero is still.
+pub const ATTRIBUTE_LIQUID_FLOW: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_LiquidFlow", 988_540_917, VertexFormat::Float32x3);
+
+/// How many voxels of liquid there are under a vertex, its own voxel included.
+pub const ATTRIBUTE_LIQUID_DEPTH: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_LiquidDepth", 988_540_918, VertexFormat::Float32);
+
+/// Marks the entities that draw a chunk's liquids, so their material can be swapped
+/// when `Settings::water_shader` changes.
+#[derive(Component)]
+pub struct LiquidMesh;
+
+/// The material liquids are drawn with when `Settings::water_shader` is on.
+/// Waves ripple over the surface, the texture drifts the way the liquid flows,
+/// and deep liquid gets darker than shallow liquid. The vertex colours light it like every other block.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct WaterMaterial {
+    /// Tint of liquid one voxel deep.
+    #[uniform(0)]
+    pub shallow_color: Color,
+    /// Tint of liquid `deep_depth` voxels deep or more.
+    #[uniform(1)]
+    pub deep_color: Color,
+    /// How many voxels deep the liquid has to be to get `deep_color`.
+    #[uniform(2)]
+    pub deep_depth: f32,
+    /// How many tiles a second the texture moves for every unit of flow.
+    #[uniform(3)]
+    pub flow_speed: f32,
+    /// How tall the waves on the surface are, in how far they tilt the normal.
+    #[uniform(4)]
+    pub wave_strength: f32,
+    /// How fast the waves move.
+    #[uniform(5)]
+    pub wave_speed: f32,
+    /// How big a tile of the atlas is in UV space.
+    /// Needed to keep the texture inside its tile while it moves.
+    #[uniform(6)]
+    pub tile_size: Vec2,
+    #[texture(7)]
+    #[sampler(8)]
+    pub texture: Handle<Image>,
+}
+
+impl WaterMaterial {
+    pub fn new(texture: Handle<Image>) -> Self {
+        Self {
+            shallow_color: Color::rgba(0.55, 0.8, 0.95, 0.7),
+            deep_color: Color::rgba(0.1, 0.25, 0.45, 0.95),
+            deep_depth: 8.0,
+            flow_speed: 0.6,
+            wave_strength: 0.15,
+            wave_speed: 1.2,
+            tile_size: Vec2::ONE,
+            texture,
+        }
+    }
+}
+
+impl Material for WaterMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/water.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/water.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+            ATTRIBUTE_LIQUID_FLOW.at_shader_location(3),
+            ATTRIBUTE_LIQUID_DEPTH.at_shader_location(4),
+            Mesh::ATTRIBUTE_UV_1.at_shader_location(5),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(6),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+
+        // Liquids are seen from underneath too
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
+}
+
+/// Tells the water material how big the tiles of the atlas are, once the blocks are known.
+pub fn update_water_atlas(
+    blocks: Res<BlockRegistry>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
+) {
+    let Some(material) = water_materials.get_mut(&chunk_materials.water) else {
+        return;
+    };
+
+    material.tile_size = Vec2::from(blocks.atlas.uv_rect((0, 0)).size());
+}
+
+/// Swaps the material of every liquid mesh when `Settings::water_shader` changes.
+/// New meshes pick the right one when they're spawned.
+pub fn apply_water_setting(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    chunk_materials: Res<ChunkMaterials>,
+    query: Query<Entity, With<LiquidMesh>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for entity in query.iter() {
+        let mut entity = commands.entity(entity);
+        if settings.water_shader {
+            entity.remove::<Handle<BlockMaterial>>().insert(chunk_materials.water.clone());
+        } else {
+            entity.remove::<Handle<WaterMaterial>>().insert(chunk_materials.liquid.clone());
+        }
+    }
+}
@@ -1 +1,11 @@
-pub mod world;
\ No newline at end of file
+pub mod world;
+pub mod region;
+pub mod caves;
+pub mod biome;
+pub mod surface;
+pub mod pipeline;
+pub mod features;
+pub mod ores;
+pub mod config;
+pub mod generator;
+pub mod heightmap;
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use splines::{Interpolation, Key, Spline};
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::features::{Blueprint, Vegetation};
+
+/// Index of a biome inside the BiomeRegistry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BiomeId(pub usize);
+
+/// Describes what a biome looks like and where it shows up.
+#[derive(Clone)]
+pub struct Biome {
+    pub name: String,
+    /// Where the biome sits in the climate. Both go from -1 (cold, dry) to 1 (hot, wet).
+    /// Every column picks the biome closest to its own climate.
+    pub temperature: f64,
+    pub humidity: f64,
+    /// Turns the verticality noise into a height multiplier, like the old `spline_points`.
+    pub height_spline: Spline<f64, f64>,
+    /// The top voxel of every column.
+    pub surface_block: VoxelType,
+    /// The few voxels under the surface.
+    pub subsurface_block: VoxelType,
+    /// Everything above the terrain up to this height is water.
+    pub water_level: f64,
+    /// What grows on the surface block. Densities add up, so they shouldn't go over 1 together.
+    pub vegetation: Vec<Vegetation>,
+}
+
+/// All the biomes the world generator can pick from.
+/// Other plugins add their own through `App::register_biome`.
+#[derive(Resource, Clone)]
+pub struct BiomeRegistry {
+    biomes: Vec<Biome>,
+}
+
+impl Default for BiomeRegistry {
+    fn default() -> Self {
+        let tree = Arc::new(Blueprint::tree(5, 2));
+        let tall_tree = Arc::new(Blueprint::tree(7, 2));
+        let bush = Arc::new(Blueprint::bush());
+        let tall_grass = Arc::new(Blueprint::single(VoxelType::TALL_GRASS));
+
+        let mut registry = Self::empty();
+        registry.register(Biome {
+            name: "plains".into(),
+            temperature: 0.0,
+            humidity: 0.0,
+            height_spline: linear_spline(&[
+                (-1.0, 0.3), (-0.6, 0.35), (-0.4, 1.0), (-0.2, 1.0), (0.0, 1.3), (0.1, 1.4), (0.2, 1.4),
+                (0.3, 1.4), (0.4, 1.7), (0.5, 2.0), (0.6, 2.1), (0.7, 3.0), (1.1, 3.0),
+            ]),
+            surface_block: VoxelType::GRASS,
+            subsurface_block: VoxelType::DIRT,
+            water_level: 20.0,
+            vegetation: vec![
+                Vegetation { blueprints: vec![tree.clone()], density: 0.002 },
+                Vegetation { blueprints: vec![bush.clone()], density: 0.004 },
+                Vegetation { blueprints: vec![tall_grass.clone()], density: 0.1 },
+            ],
+        });
+        registry.register(Biome {
+            name: "forest".into(),
+            temperature: 0.3,
+            humidity: 0.6,
+            height_spline: linear_spline(&[(-1.0, 0.5), (-0.3, 1.0), (0.3, 1.6), (0.7, 2.2), (1.1, 2.4)]),
+            surface_block: VoxelType::GRASS,
+            subsurface_block: VoxelType::DIRT,
+            water_level: 20.0,
+            vegetation: vec![
+                Vegetation { blueprints: vec![tree.clone(), tall_tree.clone()], density: 0.025 },
+                Vegetation { blueprints: vec![bush], density: 0.01 },
+                Vegetation { blueprints: vec![tall_grass], density: 0.05 },
+            ],
+        });
+        registry.register(Biome {
+            name: "desert".into(),
+            temperature: 0.8,
+            humidity: -0.7,
+            height_spline: linear_spline(&[(-1.0, 0.2), (-0.2, 0.5), (0.4, 0.8), (1.1, 1.0)]),
+            surface_block: VoxelType::SAND,
+            subsurface_block: VoxelType::SAND,
+            water_level: 12.0,
+            vegetation: Vec::new(),
+        });
+        registry.register(Biome {
+            name: "snowy mountains".into(),
+            temperature: -0.8,
+            humidity: 0.0,
+            height_spline: linear_spline(&[(-1.0, 0.8), (-0.4, 1.5), (0.2, 2.5), (0.6, 3.5), (1.1, 4.0)]),
+            surface_block: VoxelType::SNOW,
+            subsurface_block: VoxelType::STONE,
+            water_level: 20.0,
+            vegetation: vec![
+                Vegetation { blueprints: vec![tall_tree], density: 0.003 },
+            ],
+        });
+        registry
+    }
+}
+
+impl BiomeRegistry {
+    /// A registry without any biomes, for when the built-in ones aren't wanted.
+    pub fn empty() -> Self {
+        Self {
+            biomes: Vec::new(),
+        }
+    }
+
+    /// Adds a biome. A biome with the same name as an existing one replaces it.
+    pub fn register(&mut self, biome: Biome) -> BiomeId {
+        if let Some(index) = self.biomes.iter().position(|existing| existing.name == biome.name) {
+            self.biomes[index] = biome;
+            return BiomeId(index);
+        }
+
+        self.biomes.push(biome);
+        BiomeId(self.biomes.len() - 1)
+    }
+
+    pub fn get(&self, id: BiomeId) -> &Biome {
+        &self.biomes[id.0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BiomeId, &Biome)> {
+        self.biomes.iter().enumerate().map(|(index, biome)| (BiomeId(index), biome))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.biomes.is_empty()
+    }
+}
+
+/// Lets plugins add biomes while the app is being built.
+pub trait RegisterBiome {
+    fn register_biome(&mut self, biome: Biome) -> &mut Self;
+}
+
+impl RegisterBiome for App {
+    fn register_biome(&mut self, biome: Biome) -> &mut Self {
+        self.init_resource::<BiomeRegistry>();
+        self.world.resource_mut::<BiomeRegistry>().register(biome);
+        self
+    }
+}
+
+/// What the biome layer decided for a single column.
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnBiome {
+    /// The biome closest to the column's climate. Decides the blocks.
+    pub dominant: BiomeId,
+    /// The height multiplier, blended between the nearby biomes.
+    pub height_scale: f64,
+    /// The water level of the dominant biome. Not blended, water has to stay flat.
+    pub water_level: f64,
+}
+
+/// Picks biomes out of temperature and humidity noise.
+#[derive(Clone)]
+pub struct BiomeMap {
+    pub registry: BiomeRegistry,
+    temperature: Fbm<Perlin>,
+    humidity: Fbm<Perlin>,
+    /// How close in climate another biome has to be to the dominant one to blend in.
+    /// Bigger values give wider, smoother borders.
+    pub blend: f64,
+    /// Roughly how big biomes are, in voxels.
+    pub scale: f64,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u32, registry: BiomeRegistry) -> Self {
+        Self {
+            registry,
+            temperature: Fbm::<Perlin>::new(seed.wrapping_add(10)).set_octaves(3),
+            humidity: Fbm::<Perlin>::new(seed.wrapping_add(11)).set_octaves(3),
+            blend: 0.15,
+            scale: 1024.0,
+        }
+    }
+
+    /// Temperature and humidity at a world column, roughly between -1 and 1.
+    pub fn climate(&self, wx: f64, wz: f64) -> (f64, f64) {
+        let point = [wx / self.scale, wz / self.scale];
+        // Fbm rarely gets near its extremes, so stretch it out a bit
+        let temperature = (self.temperature.get(point) * 1.6).clamp(-1.0, 1.0);
+        let humidity = (self.humidity.get(point) * 1.6).clamp(-1.0, 1.0);
+        (temperature, humidity)
+    }
+
+    /// Decides the biome of a world column.
+    ///
+    /// * `verticality`: The verticality noise at this column, fed into the height splines.
+    pub fn column(&self, wx: f64, wz: f64, verticality: f64) -> ColumnBiome {
+        let (temperature, humidity) = self.climate(wx, wz);
+        let distances: Vec<(BiomeId, f64)> = self.registry.iter()
+            .map(|(id, biome)| {
+                let dt = biome.temperature - temperature;
+                let dh = biome.humidity - humidity;
+                (id, (dt * dt + dh * dh).sqrt())
+            })
+            .collect();
+
+        let (dominant, nearest) = distances.iter()
+            .copied()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("the biome registry is empty");
+
+        // Every biome within `blend` of the nearest one gets a say, fading out linearly.
+        // Weights reach zero right as a biome drops out, so there are no sudden jumps.
+        let mut total_weight = 0.0;
+        let mut height_scale = 0.0;
+        for (id, distance) in distances {
+            let weight = (self.blend - (distance - nearest)).max(0.0);
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let biome = self.registry.get(id);
+            total_weight += weight;
+            height_scale += weight * biome.height_spline.clamped_sample(verticality).unwrap_or(1.0);
+        }
+
+        // A blended water level would slope across borders and hang over the columns next to it
+        let biome = self.registry.get(dominant);
+
+        // Only happens with a blend of zero, in which case the nearest biome wins outright
+        if total_weight <= 0.0 {
+            return ColumnBiome {
+                dominant,
+                height_scale: biome.height_spline.clamped_sample(verticality).unwrap_or(1.0),
+                water_level: biome.water_level,
+            };
+        }
+
+        ColumnBiome {
+            dominant,
+            height_scale: height_scale / total_weight,
+            water_level: biome.water_level,
+        }
+    }
+}
+
+/// Builds a spline out of (verticality, height multiplier) points.
+/// Points have to be sorted by verticality.
+pub fn linear_spline(points: &[(f64, f64)]) -> Spline<f64, f64> {
+    Spline::from_vec(points.iter()
+        .map(|(t, value)| Key::new(*t, *value, Interpolation::Linear))
+        .collect())
+}
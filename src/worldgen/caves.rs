@@ -0,0 +1,92 @@
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+/// Tunables for the caves.
+#[derive(Debug, Clone, Copy)]
+pub struct CaveSettings {
+    /// How much gets carved out, from 0 (no caves at all) to 1 (swiss cheese).
+    pub density: f64,
+    /// Caves stay at least this many voxels below the surface.
+    pub min_depth: i32,
+    /// Voxels of stone kept between caves and any water,
+    /// so the seas and lakes don't end up with holes in them.
+    pub water_margin: i32,
+    /// Roughly how big the cheese caves are, in voxels.
+    pub cheese_scale: f64,
+    /// Roughly how far the spaghetti tunnels go before turning, in voxels.
+    pub spaghetti_scale: f64,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            density: 0.4,
+            min_depth: 6,
+            water_margin: 3,
+            cheese_scale: 96.0,
+            spaghetti_scale: 64.0,
+        }
+    }
+}
+
+/// Carves caves out of the terrain using 3D noise.
+///
+/// Cheese caves are the big open chambers, carved wherever the cheese noise is high.
+/// Spaghetti caves are long winding tunnels, carved where two separate noises are
+/// both close to zero. Each of those is a surface, and where they cross is a line.
+#[derive(Clone)]
+pub struct CaveCarver {
+    pub settings: CaveSettings,
+    cheese: Fbm<Perlin>,
+    spaghetti: [Perlin; 2],
+}
+
+impl CaveCarver {
+    pub fn new(seed: u32, settings: CaveSettings) -> Self {
+        Self {
+            settings,
+            cheese: Fbm::<Perlin>::new(seed).set_octaves(3).set_persistence(0.5),
+            spaghetti: [Perlin::new(seed.wrapping_add(1)), Perlin::new(seed.wrapping_add(2))],
+        }
+    }
+
+    /// Whether the voxel at a world position should be carved out.
+    /// Doesn't care about the surface or water, that's up to the caller.
+    pub fn is_cave(&self, wx: f64, wy: f64, wz: f64) -> bool {
+        let density = self.settings.density.clamp(0.0, 1.0);
+        if density <= 0.0 {
+            return false;
+        }
+
+        // Squash things vertically a bit, caves are wider than they are tall
+        let scale = self.settings.cheese_scale;
+        let cheese = self.cheese.get([wx / scale, wy / (scale * 0.6), wz / scale]);
+        // The cheese noise is almost never above 0.5, and is above 0.1 about a third of the time
+        if cheese > 0.5 - 0.4 * density {
+            return true;
+        }
+
+        let scale = self.settings.spaghetti_scale;
+        let point = [wx / scale, wy / (scale * 0.75), wz / scale];
+        let width = 0.1 * density;
+        self.spaghetti[0].get(point).abs() < width && self.spaghetti[1].get(point).abs() < width
+    }
+
+    /// The highest a cave can go in a column.
+    ///
+    /// * `surface`: Height of the terrain in the column.
+    /// * `nearby_columns`: Height and water level of every column within
+    ///   `water_margin` of this one, including itself.
+    pub fn ceiling(&self, surface: f64, nearby_columns: impl Iterator<Item = (f64, f64)>) -> f64 {
+        let margin = self.settings.water_margin as f64;
+        let mut ceiling = surface - self.settings.min_depth as f64;
+
+        // Any column with water in it pushes the caves down below its floor
+        for (nearby, water_level) in nearby_columns {
+            if nearby < water_level {
+                ceiling = ceiling.min(nearby - margin);
+            }
+        }
+
+        ceiling
+    }
+}
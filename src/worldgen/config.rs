@@ -0,0 +1,237 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use bevy::prelude::*;
+use serde::Deserialize;
+use crate::worldgen::generator::SuperflatSettings;
+use crate::worldgen::heightmap::HeightmapSettings;
+
+/// Seed used when none is given.
+pub const DEFAULT_SEED: u32 = 6346547;
+
+/// The presets that ship with the game, see `WorldGenConfig::preset`.
+pub const PRESETS: [&str; 6] = ["default", "amplified", "flat", "islands", "superflat", "void"];
+
+/// Parameters of a fractal noise.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct NoiseSettings {
+    pub octaves: usize,
+    /// How much the frequency goes up with every octave.
+    pub lacunarity: f64,
+    /// How much the amplitude goes down with every octave.
+    pub persistence: f64,
+    /// Roughly how wide the features of the noise are, in voxels.
+    pub scale: f64,
+}
+
+/// Everything that decides what the terrain looks like.
+/// The same config always generates the same world.
+///
+/// Loaded from a `.ron` file, or put together from one of the `PRESETS`
+/// and a seed, see `WorldGenConfig::from_args`.
+#[derive(Resource, Debug, Clone, Deserialize)]
+pub struct WorldGenConfig {
+    /// The preset this started out as, so testers can tell others how to get the same world.
+    /// Configs loaded from a file are "custom" unless they say otherwise.
+    #[serde(default = "WorldGenConfig::custom_preset")]
+    pub preset: String,
+    /// Name of the ChunkGenerator to use, see `ChunkGenerators`.
+    /// Everything below the seed is only used by the "noise" generator.
+    #[serde(default = "WorldGenConfig::default_generator")]
+    pub generator: String,
+    pub seed: u32,
+    /// The noise that shapes the hills.
+    pub terrain: NoiseSettings,
+    /// How high the terrain goes, in voxels, before any multipliers.
+    pub amplitude: f64,
+    /// Added to the height of every column. Negative values sink the world into the sea.
+    #[serde(default)]
+    pub height_offset: f64,
+    /// Roughly how wide the features of the verticality noise are, in voxels.
+    pub verticality_scale: f64,
+    /// Turns the verticality noise into a height multiplier, on top of the one from the biome.
+    /// Given as (verticality, multiplier) points, with verticality going from -1 to 1.
+    pub height_spline: Vec<(f64, f64)>,
+    /// Only used by the "superflat" generator.
+    #[serde(default)]
+    pub superflat: SuperflatSettings,
+    /// Only used by the "heightmap" generator, which uses the rest of the noise settings
+    /// for the biomes and caves.
+    #[serde(default)]
+    pub heightmap: HeightmapSettings,
+}
+
+impl Default for WorldGenConfig {
+    fn default() -> Self {
+        Self {
+            preset: "default".into(),
+            generator: Self::default_generator(),
+            seed: DEFAULT_SEED,
+            terrain: NoiseSettings {
+                octaves: 6,
+                lacunarity: 2.0,
+                persistence: 0.5,
+                scale: 409.6,
+            },
+            amplitude: 128.0,
+            height_offset: 0.0,
+            verticality_scale: 409.6,
+            height_spline: vec![(-1.0, 1.0), (1.1, 1.0)],
+            superflat: SuperflatSettings::default(),
+            heightmap: HeightmapSettings::default(),
+        }
+    }
+}
+
+impl WorldGenConfig {
+    fn custom_preset() -> String {
+        "custom".into()
+    }
+
+    fn default_generator() -> String {
+        "noise".into()
+    }
+
+    /// One of the `PRESETS`, with the default seed.
+    pub fn preset(name: &str) -> Option<Self> {
+        let default = Self::default();
+        let config = match name {
+            "default" => default,
+            // Taller, rougher mountains that get even taller where the verticality is high
+            "amplified" => Self {
+                terrain: NoiseSettings { persistence: 0.55, ..default.terrain },
+                amplitude: 256.0,
+                height_spline: vec![(-1.0, 0.8), (0.0, 1.0), (0.5, 1.8), (1.1, 2.5)],
+                ..default
+            },
+            // Gentle rolling plains, just above the water
+            "flat" => Self {
+                terrain: NoiseSettings { octaves: 2, ..default.terrain },
+                amplitude: 8.0,
+                height_offset: 24.0,
+                ..default
+            },
+            // Mostly sea, with the tops of the hills sticking out of it
+            "islands" => Self {
+                terrain: NoiseSettings { scale: 300.0, ..default.terrain },
+                amplitude: 160.0,
+                height_offset: -48.0,
+                ..default
+            },
+            "superflat" => Self {
+                generator: "superflat".into(),
+                ..default
+            },
+            "void" => Self {
+                generator: "void".into(),
+                ..default
+            },
+            _ => return None,
+        };
+
+        Some(Self {
+            preset: name.into(),
+            ..config
+        })
+    }
+
+    /// Name of the directory the world is saved in, like `default-noise-6346547-1f2e3d4c`.
+    /// Every config gets its own, so changing the seed, preset or generator never loads
+    /// chunks that were generated with another one. The hash at the end covers the rest
+    /// of the config, for configs loaded from a file that only differ in their settings.
+    pub fn save_name(&self) -> String {
+        // FNV-1a, it only has to stay the same between runs
+        let mut hash: u32 = 0x811c9dc5;
+        for byte in format!("{self:?}").bytes() {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+
+        let clean = |name: &str| -> String {
+            name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+        };
+        format!("{}-{}-{}-{hash:08x}", clean(&self.preset), clean(&self.generator), self.seed)
+    }
+
+    /// Reads a config from a `.ron` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, WorldGenConfigError> {
+        let text = fs::read_to_string(path).map_err(WorldGenConfigError::Io)?;
+        ron::from_str(&text).map_err(WorldGenConfigError::Parse)
+    }
+
+    /// Puts a config together from command line arguments:
+    ///
+    /// * `--preset <name>`: Start from one of the `PRESETS`. The default is "default".
+    /// * `--worldgen <file>`: Start from a config file instead.
+    /// * `--seed <seed>`: Use another seed.
+    /// * `--generator <name>`: Use another generator, like one added by a plugin.
+    /// * `--heightmap <file>`: Shape the terrain after a heightmap image, see `HeightmapSettings`.
+    ///
+    /// Anything else is left for someone else to deal with.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, WorldGenConfigError> {
+        let mut preset = None;
+        let mut file = None;
+        let mut seed = None;
+        let mut generator = None;
+        let mut heightmap = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "--preset" => &mut preset,
+                "--worldgen" => &mut file,
+                "--seed" => &mut seed,
+                "--generator" => &mut generator,
+                "--heightmap" => &mut heightmap,
+                _ => continue,
+            };
+            *target = Some(args.next().ok_or(WorldGenConfigError::MissingValue(arg))?);
+        }
+
+        let mut config = match (preset, file) {
+            (Some(_), Some(_)) => return Err(WorldGenConfigError::PresetAndFile),
+            (None, Some(file)) => Self::load(file)?,
+            (Some(preset), None) => Self::preset(&preset).ok_or(WorldGenConfigError::UnknownPreset(preset))?,
+            (None, None) => Self::default(),
+        };
+
+        if let Some(seed) = seed {
+            config.seed = seed.parse().map_err(|_| WorldGenConfigError::InvalidSeed(seed))?;
+        }
+        if let Some(heightmap) = heightmap {
+            config.generator = "heightmap".into();
+            config.heightmap.path = heightmap;
+        }
+        if let Some(generator) = generator {
+            config.generator = generator;
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum WorldGenConfigError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    UnknownPreset(String),
+    InvalidSeed(String),
+    MissingValue(String),
+    PresetAndFile,
+}
+
+impl fmt::Display for WorldGenConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldGenConfigError::Io(err) => write!(f, "could not read the world generation config: {err}"),
+            WorldGenConfigError::Parse(err) => write!(f, "could not parse the world generation config: {err}"),
+            WorldGenConfigError::UnknownPreset(name) => {
+                write!(f, "there is no preset called \"{name}\", pick one of {}", PRESETS.join(", "))
+            }
+            WorldGenConfigError::InvalidSeed(seed) => write!(f, "\"{seed}\" is not a valid seed"),
+            WorldGenConfigError::MissingValue(arg) => write!(f, "{arg} needs a value"),
+            WorldGenConfigError::PresetAndFile => write!(f, "--preset and --worldgen can't be used together"),
+        }
+    }
+}
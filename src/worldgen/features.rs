@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use bevy::math::IVec3;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::voxel::util::CHUNK_SIZE;
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::biome::{Biome, BiomeRegistry};
+use crate::worldgen::pipeline::{ChunkNeighbourhood, Feature};
+use crate::worldgen::world::ColumnMap;
+
+/// A small structure made out of voxels, like a tree.
+#[derive(Debug, Clone, Default)]
+pub struct Blueprint {
+    /// Offsets from the voxel right above the ground the blueprint grows out of.
+    pub voxels: Vec<(IVec3, VoxelType)>,
+}
+
+impl Blueprint {
+    /// A single voxel, like a tuft of tall grass.
+    pub fn single(voxel_type: VoxelType) -> Self {
+        Self {
+            voxels: vec![(IVec3::ZERO, voxel_type)],
+        }
+    }
+
+    /// A trunk of WOOD with a round crown of LEAVES around the top of it.
+    pub fn tree(trunk_height: i32, crown_radius: i32) -> Self {
+        let mut voxels: Vec<(IVec3, VoxelType)> = (0..trunk_height)
+            .map(|y| (IVec3::new(0, y, 0), VoxelType::WOOD))
+            .collect();
+
+        // Flattened a bit, and sitting mostly above the top of the trunk
+        let top = IVec3::new(0, trunk_height - 1, 0);
+        let radius = crown_radius as f32 + 0.5;
+        for x in -crown_radius..=crown_radius {
+            for y in -1..=crown_radius {
+                for z in -crown_radius..=crown_radius {
+                    let stretched_y = y as f32 * 1.4;
+                    if (x * x + z * z) as f32 + stretched_y * stretched_y <= radius * radius {
+                        voxels.push((top + IVec3::new(x, y, z), VoxelType::LEAVES));
+                    }
+                }
+            }
+        }
+
+        Self { voxels }
+    }
+
+    /// A low clump of LEAVES.
+    pub fn bush() -> Self {
+        let voxels = [IVec3::ZERO, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z, IVec3::Y]
+            .into_iter()
+            .map(|offset| (offset, VoxelType::LEAVES))
+            .collect();
+
+        Self { voxels }
+    }
+
+    /// Places the blueprint with its origin at a world position.
+    /// Voxels only go where they're stronger than what's already there,
+    /// see `strength`. Anything outside the neighbourhood is cut off.
+    pub fn place(&self, origin: IVec3, neighbourhood: &mut ChunkNeighbourhood) {
+        for (offset, voxel_type) in self.voxels.iter() {
+            let world_pos = origin + *offset;
+            let Some(existing) = neighbourhood.get(world_pos) else {
+                continue;
+            };
+
+            if let (Some(new), Some(old)) = (strength(*voxel_type), strength(existing)) {
+                if new > old {
+                    neighbourhood.set(world_pos, *voxel_type);
+                }
+            }
+        }
+    }
+}
+
+/// How strongly a block holds on to its spot when blueprints overlap.
+/// Blueprints only ever replace weaker blocks, and blocks without a strength,
+/// like the ground or water, can't be replaced at all. The strongest block
+/// wins no matter which blueprint went first, so overlapping trees
+/// come out the same whatever order the chunks were decorated in.
+fn strength(voxel_type: VoxelType) -> Option<u8> {
+    match voxel_type {
+        VoxelType::AIR => Some(0),
+        VoxelType::TALL_GRASS => Some(1),
+        VoxelType::LEAVES => Some(2),
+        VoxelType::WOOD => Some(3),
+        _ => None,
+    }
+}
+
+/// Something a biome grows, and how often.
+#[derive(Debug, Clone)]
+pub struct Vegetation {
+    /// One of these is picked at random for every plant.
+    pub blueprints: Vec<Arc<Blueprint>>,
+    /// The chance of every column getting one, from 0 to 1.
+    pub density: f64,
+}
+
+/// Scatters the vegetation of each biome over the surface.
+///
+/// Every chunk gets its own random generator, seeded with the world seed and
+/// its position, so a chunk always grows the same plants. Plants only grow on
+/// the surface block of their biome, so not on beaches or underwater, and the
+/// chunk the ground is in is the one that places them.
+pub struct VegetationFeature {
+    seed: u32,
+    biomes: BiomeRegistry,
+}
+
+impl VegetationFeature {
+    pub fn new(seed: u32, biomes: BiomeRegistry) -> Self {
+        Self { seed, biomes }
+    }
+
+    fn chunk_seed(&self, chunk_pos: IVec3) -> u64 {
+        // FNV-1a over the seed and the position
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for value in [self.seed as i32, chunk_pos.x, chunk_pos.y, chunk_pos.z] {
+            hash ^= value as u32 as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+/// Picks the vegetation a roll between 0 and 1 lands on, if any.
+fn pick_vegetation(biome: &Biome, roll: f64) -> Option<&Vegetation> {
+    let mut total = 0.0;
+    biome.vegetation.iter().find(|vegetation| {
+        total += vegetation.density;
+        roll < total
+    })
+}
+
+impl Feature for VegetationFeature {
+    fn place(&self, chunk_pos: IVec3, columns: &ColumnMap, neighbourhood: &mut ChunkNeighbourhood) {
+        let origin = neighbourhood.origin();
+        let mut rng = StdRng::seed_from_u64(self.chunk_seed(chunk_pos));
+
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                // Rolled for every column, so skipping one doesn't change what the others get
+                let roll: f64 = rng.gen();
+                let variant: usize = rng.gen();
+
+                let column = columns.get(x, z);
+                let ground_y = column.height.floor() as i32;
+                if ground_y.div_euclid(CHUNK_SIZE) != chunk_pos.y || column.height < column.biome.water_level {
+                    continue;
+                }
+
+                let biome = self.biomes.get(column.biome.dominant);
+                let Some(vegetation) = pick_vegetation(biome, roll) else {
+                    continue;
+                };
+                if vegetation.blueprints.is_empty() {
+                    continue;
+                }
+
+                // Caves might have eaten the ground. Only the ground is checked, and not
+                // what's on top of it, since other plants might or might not be there yet.
+                let ground = IVec3::new(origin.x + x, ground_y, origin.z + z);
+                if neighbourhood.get(ground) != Some(biome.surface_block)
+                    || neighbourhood.get(ground + IVec3::Y).and_then(strength).is_none()
+                {
+                    continue;
+                }
+
+                let blueprint = &vegetation.blueprints[variant % vegetation.blueprints.len()];
+                blueprint.place(ground + IVec3::Y, neighbourhood);
+            }
+        }
+    }
+}
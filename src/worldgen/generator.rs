@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use bevy::prelude::*;
+use serde::Deserialize;
+use crate::voxel::chunk::Chunk;
+use crate::voxel::util::CHUNK_SIZE;
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::biome::{BiomeId, BiomeRegistry};
+use crate::worldgen::config::WorldGenConfig;
+use crate::worldgen::heightmap::Heightmap;
+use crate::worldgen::ores::OreRegistry;
+use crate::worldgen::pipeline::{ChunkStatus, ProtoChunk, StageInput, StageOutput};
+use crate::worldgen::world::TerrainGenerator;
+
+/// Decides what goes into the chunks of a world.
+/// Doesn't touch the world itself, so it can be used from other threads.
+pub trait ChunkGenerator: Send + Sync {
+    /// Moves a chunk through its next stage, see `ChunkStatus`.
+    /// Generators that don't need every stage can leave the chunk alone for those.
+    fn run_stage(&self, input: StageInput) -> StageOutput;
+
+    /// Samples a column without generating any chunks, for tools like the worldgen CLI.
+    /// Generators without any ground to speak of, like the void, return None.
+    fn sample_column(&self, _wx: f64, _wz: f64) -> Option<ColumnSample> {
+        None
+    }
+
+    /// The biomes `sample_column` refers to, if the generator has any.
+    fn biomes(&self) -> Option<&BiomeRegistry> {
+        None
+    }
+
+    /// Generates a chunk on its own, running every stage
+    /// that doesn't need the neighbours. Handy for tools and tests.
+    fn generate_chunk(&self, chunk_pos: IVec3) -> Chunk {
+        let mut proto = ProtoChunk::new(Chunk::new(chunk_pos), ChunkStatus::Empty);
+        while let Some(stage) = proto.status.next().filter(|stage| !stage.touches_neighbours()) {
+            let output = self.run_stage(StageInput { chunk: proto, neighbourhood: None });
+            proto = ProtoChunk {
+                chunk: output.chunk,
+                status: stage,
+                columns: output.columns,
+            };
+        }
+
+        let mut chunk = Arc::unwrap_or_clone(proto.chunk);
+        chunk.compact();
+        chunk
+    }
+}
+
+/// What a generator can tell about a column up front.
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnSample {
+    /// Height of the ground. The top voxel is the highest one at or below it.
+    pub height: f64,
+    /// Everything above the ground up to this height is water.
+    /// None if the generator doesn't do water.
+    pub water_level: Option<f64>,
+    pub biome: Option<BiomeId>,
+}
+
+/// Runs a stage that only ever changes the chunk itself.
+/// For generators that don't need neighbours or columns.
+pub fn run_local_stage(proto: ProtoChunk, stage: impl FnOnce(ChunkStatus, &mut Chunk)) -> StageOutput {
+    let status = proto.status.next().unwrap_or(ChunkStatus::FINISHED);
+    let mut chunk = proto.chunk;
+
+    stage(status, Arc::make_mut(&mut chunk));
+    if status == ChunkStatus::FINISHED {
+        Arc::make_mut(&mut chunk).compact();
+    }
+
+    StageOutput {
+        position: chunk.position,
+        status,
+        chunk,
+        columns: proto.columns,
+        neighbours: Vec::new(),
+    }
+}
+
+/// Builds a generator out of whatever resources it needs.
+pub type GeneratorFactory = Box<dyn Fn(&World) -> Arc<dyn ChunkGenerator> + Send + Sync>;
+
+/// Every generator a world can be made with, by name.
+/// The world uses the one named by `WorldGenConfig::generator`.
+/// Other plugins add their own through `App::register_chunk_generator`.
+#[derive(Resource)]
+pub struct ChunkGenerators {
+    factories: HashMap<String, GeneratorFactory>,
+}
+
+impl Default for ChunkGenerators {
+    fn default() -> Self {
+        let mut generators = Self {
+            factories: HashMap::new(),
+        };
+
+        generators.register("noise", |world| {
+            let config = world.resource::<WorldGenConfig>();
+            let ores = world.resource::<OreRegistry>();
+            let biomes = world.resource::<BiomeRegistry>();
+            if biomes.is_empty() {
+                error!("No biomes were registered, using the built-in ones instead");
+                return Arc::new(TerrainGenerator::new(config.clone(), BiomeRegistry::default(), ores));
+            }
+
+            Arc::new(TerrainGenerator::new(config.clone(), biomes.clone(), ores))
+        });
+        generators.register("heightmap", |world| {
+            let config = world.resource::<WorldGenConfig>();
+            let ores = world.resource::<OreRegistry>();
+            let biomes = world.resource::<BiomeRegistry>();
+            let biomes = if biomes.is_empty() { BiomeRegistry::default() } else { biomes.clone() };
+
+            match Heightmap::load(config.heightmap.clone()) {
+                Ok(heightmap) => Arc::new(TerrainGenerator::with_heightmap(config.clone(), biomes, ores, heightmap)),
+                Err(err) => {
+                    error!("Could not load the heightmap {}, using the noise instead: {err}", config.heightmap.path);
+                    Arc::new(TerrainGenerator::new(config.clone(), biomes, ores))
+                }
+            }
+        });
+        generators.register("superflat", |world| {
+            Arc::new(SuperflatGenerator::new(world.resource::<WorldGenConfig>().superflat.clone()))
+        });
+        generators.register("void", |_| Arc::new(VoidGenerator));
+
+        generators
+    }
+}
+
+impl ChunkGenerators {
+    /// Adds a generator. A generator with the same name as an existing one replaces it.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(&World) -> Arc<dyn ChunkGenerator> + Send + Sync + 'static,
+    ) {
+        self.factories.insert(name.into(), Box::new(factory));
+    }
+
+    /// Builds the generator with the given name, if there is one.
+    pub fn build(&self, name: &str, world: &World) -> Option<Arc<dyn ChunkGenerator>> {
+        self.factories.get(name).map(|factory| factory(world))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+}
+
+/// Lets plugins add generators while the app is being built.
+pub trait RegisterChunkGenerator {
+    fn register_chunk_generator(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(&World) -> Arc<dyn ChunkGenerator> + Send + Sync + 'static,
+    ) -> &mut Self;
+}
+
+impl RegisterChunkGenerator for App {
+    fn register_chunk_generator(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(&World) -> Arc<dyn ChunkGenerator> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.init_resource::<ChunkGenerators>();
+        self.world.resource_mut::<ChunkGenerators>().register(name, factory);
+        self
+    }
+}
+
+/// A layer of a superflat world.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct FlatLayer {
+    pub block: VoxelType,
+    /// How many voxels thick the layer is.
+    pub thickness: u32,
+}
+
+/// Tunables for the superflat generator.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SuperflatSettings {
+    /// From the bottom up.
+    pub layers: Vec<FlatLayer>,
+    /// World height the bottom of the lowest layer sits at. Everything under it is AIR.
+    pub base_height: i32,
+}
+
+impl Default for SuperflatSettings {
+    fn default() -> Self {
+        Self {
+            layers: vec![
+                FlatLayer { block: VoxelType::STONE, thickness: 28 },
+                FlatLayer { block: VoxelType::DIRT, thickness: 3 },
+                FlatLayer { block: VoxelType::GRASS, thickness: 1 },
+            ],
+            base_height: 0,
+        }
+    }
+}
+
+/// Stacks the same layers everywhere. Great for building tests,
+/// and for performance comparisons that shouldn't depend on the terrain.
+pub struct SuperflatGenerator {
+    /// The block at every world height, starting at the base height.
+    column: Vec<VoxelType>,
+    base_height: i32,
+}
+
+impl SuperflatGenerator {
+    pub fn new(settings: SuperflatSettings) -> Self {
+        let column = settings.layers.iter()
+            .flat_map(|layer| std::iter::repeat_n(layer.block, layer.thickness as usize))
+            .collect();
+
+        Self {
+            column,
+            base_height: settings.base_height,
+        }
+    }
+
+    fn block_at(&self, wy: i32) -> VoxelType {
+        usize::try_from(wy - self.base_height)
+            .ok()
+            .and_then(|index| self.column.get(index).copied())
+            .unwrap_or(VoxelType::AIR)
+    }
+}
+
+impl ChunkGenerator for SuperflatGenerator {
+    fn run_stage(&self, input: StageInput) -> StageOutput {
+        run_local_stage(input.chunk, |status, chunk| {
+            if status != ChunkStatus::Terrain {
+                return;
+            }
+
+            let world_y = chunk.position.y * CHUNK_SIZE;
+            for y in 0..CHUNK_SIZE {
+                let voxel_type = self.block_at(world_y + y);
+                if voxel_type == VoxelType::AIR {
+                    continue;
+                }
+
+                for x in 0..CHUNK_SIZE {
+                    for z in 0..CHUNK_SIZE {
+                        chunk.set(IVec3::new(x, y, z), voxel_type);
+                    }
+                }
+            }
+        })
+    }
+
+    fn sample_column(&self, _wx: f64, _wz: f64) -> Option<ColumnSample> {
+        if self.column.is_empty() {
+            return None;
+        }
+
+        Some(ColumnSample {
+            height: (self.base_height + self.column.len() as i32 - 1) as f64,
+            water_level: None,
+            biome: None,
+        })
+    }
+}
+
+/// Nothing at all, for when the world should be built up from scratch.
+pub struct VoidGenerator;
+
+impl ChunkGenerator for VoidGenerator {
+    fn run_stage(&self, input: StageInput) -> StageOutput {
+        run_local_stage(input.chunk, |_, _| {})
+    }
+}
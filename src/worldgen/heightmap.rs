@@ -0,0 +1,209 @@
+use std::path::Path;
+use serde::Deserialize;
+
+/// What happens past the edges of a heightmap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum HeightmapEdge {
+    /// The image repeats itself. Only seamless for images made to tile.
+    Tile,
+    /// The outermost pixels stretch on forever.
+    #[default]
+    Clamp,
+}
+
+/// Tunables for the "heightmap" generator.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeightmapSettings {
+    /// A grayscale PNG, 8 or 16 bits. Colour images are turned into grayscale.
+    pub path: String,
+    /// How many voxels a pixel covers along x and z.
+    #[serde(default = "HeightmapSettings::default_horizontal_scale")]
+    pub horizontal_scale: f64,
+    /// How many voxels the terrain goes up from black to white.
+    #[serde(default = "HeightmapSettings::default_vertical_scale")]
+    pub vertical_scale: f64,
+    /// World height of black.
+    #[serde(default)]
+    pub base_height: f64,
+    /// Everything above the terrain up to this height is water, whatever the biome.
+    #[serde(default = "HeightmapSettings::default_sea_level")]
+    pub sea_level: f64,
+    #[serde(default)]
+    pub edge: HeightmapEdge,
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        Self {
+            path: "heightmap.png".into(),
+            horizontal_scale: Self::default_horizontal_scale(),
+            vertical_scale: Self::default_vertical_scale(),
+            base_height: 0.0,
+            sea_level: Self::default_sea_level(),
+            edge: HeightmapEdge::default(),
+        }
+    }
+}
+
+impl HeightmapSettings {
+    fn default_horizontal_scale() -> f64 {
+        1.0
+    }
+
+    fn default_vertical_scale() -> f64 {
+        128.0
+    }
+
+    fn default_sea_level() -> f64 {
+        20.0
+    }
+}
+
+/// A heightmap image, centered on the world origin.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    settings: HeightmapSettings,
+    width: u32,
+    height: u32,
+    /// Every pixel from 0 (black) to 1 (white), row by row.
+    pixels: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Reads the image at `settings.path`.
+    pub fn load(settings: HeightmapSettings) -> Result<Self, image::ImageError> {
+        let image = image::open(Path::new(&settings.path))?.into_luma16();
+        let (width, height) = image.dimensions();
+        let pixels = image.into_raw()
+            .into_iter()
+            .map(|pixel| pixel as f32 / u16::MAX as f32)
+            .collect();
+
+        Ok(Self::new(settings, width, height, pixels))
+    }
+
+    /// A heightmap out of pixels that are already loaded, from 0 to 1, row by row.
+    pub fn new(settings: HeightmapSettings, width: u32, height: u32, pixels: Vec<f32>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize, "the heightmap needs a pixel for every spot");
+        assert!(width > 0 && height > 0, "the heightmap can't be empty");
+
+        Self {
+            settings,
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn settings(&self) -> &HeightmapSettings {
+        &self.settings
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> f64 {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let (x, y) = match self.settings.edge {
+            HeightmapEdge::Tile => (x.rem_euclid(width), y.rem_euclid(height)),
+            HeightmapEdge::Clamp => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+        };
+
+        self.pixels[(x + y * width) as usize] as f64
+    }
+
+    /// Height of the terrain at a world column, blended between the four closest pixels
+    /// so scaled up images don't turn into stairs.
+    pub fn height(&self, wx: f64, wz: f64) -> f64 {
+        // Pixel centers sit at half pixels
+        let x = wx / self.settings.horizontal_scale + self.width as f64 / 2.0 - 0.5;
+        let y = wz / self.settings.horizontal_scale + self.height as f64 / 2.0 - 0.5;
+        let (left, top) = (x.floor(), y.floor());
+        let (tx, ty) = (x - left, y - top);
+        let (left, top) = (left as i64, top as i64);
+
+        let upper = self.pixel(left, top) * (1.0 - tx) + self.pixel(left + 1, top) * tx;
+        let lower = self.pixel(left, top + 1) * (1.0 - tx) + self.pixel(left + 1, top + 1) * tx;
+        let value = upper * (1.0 - ty) + lower * ty;
+
+        self.settings.base_height + value * self.settings.vertical_scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heightmap, HeightmapEdge, HeightmapSettings};
+
+    /// A 2x2 image, with black at the top left and white at the bottom right.
+    /// Black is at 10 and white at 110.
+    fn heightmap(edge: HeightmapEdge, horizontal_scale: f64) -> Heightmap {
+        let settings = HeightmapSettings {
+            horizontal_scale,
+            vertical_scale: 100.0,
+            base_height: 10.0,
+            edge,
+            ..Default::default()
+        };
+        Heightmap::new(settings, 2, 2, vec![0.0, 0.25, 0.5, 1.0])
+    }
+
+    fn assert_height(heightmap: &Heightmap, wx: f64, wz: f64, expected: f64) {
+        let actual = heightmap.height(wx, wz);
+        assert!((actual - expected).abs() < 1e-9, "height at {wx} {wz} is {actual}, not {expected}");
+    }
+
+    #[test]
+    fn pixel_centres_are_exact() {
+        for edge in [HeightmapEdge::Tile, HeightmapEdge::Clamp] {
+            for scale in [1.0, 4.0] {
+                let heightmap = heightmap(edge, scale);
+                // The image is centered on the origin, so the centres are half a pixel out from it
+                let half = scale / 2.0;
+                assert_height(&heightmap, -half, -half, 10.0);
+                assert_height(&heightmap, half, -half, 35.0);
+                assert_height(&heightmap, -half, half, 60.0);
+                assert_height(&heightmap, half, half, 110.0);
+            }
+        }
+    }
+
+    #[test]
+    fn blends_between_pixels() {
+        let heightmap = heightmap(HeightmapEdge::Clamp, 1.0);
+        // Halfway between two pixels, and right between all four
+        assert_height(&heightmap, 0.0, -0.5, 22.5);
+        assert_height(&heightmap, -0.5, 0.0, 35.0);
+        assert_height(&heightmap, 0.0, 0.0, 53.75);
+        // A quarter of the way
+        assert_height(&heightmap, -0.25, -0.5, 16.25);
+    }
+
+    #[test]
+    fn tile_wraps_around() {
+        let heightmap = heightmap(HeightmapEdge::Tile, 1.0);
+        for tile_x in -3..=3 {
+            for tile_z in -3..=3 {
+                let (wx, wz) = (tile_x as f64 * 2.0, tile_z as f64 * 2.0);
+                assert_height(&heightmap, wx - 0.5, wz - 0.5, 10.0);
+                assert_height(&heightmap, wx + 0.5, wz + 0.5, 110.0);
+            }
+        }
+
+        // Past the right edge it blends into the left one again
+        assert_height(&heightmap, 1.0, -0.5, 22.5);
+        assert_height(&heightmap, -1.0, -0.5, 22.5);
+        assert_height(&heightmap, -0.5, 1.0, 35.0);
+    }
+
+    #[test]
+    fn clamp_holds_the_edges() {
+        let heightmap = heightmap(HeightmapEdge::Clamp, 1.0);
+        assert_height(&heightmap, -100.0, -100.0, 10.0);
+        assert_height(&heightmap, 100.0, -100.0, 35.0);
+        assert_height(&heightmap, -100.0, 100.0, 60.0);
+        assert_height(&heightmap, 100.0, 100.0, 110.0);
+
+        // Right past the outermost pixel centres
+        assert_height(&heightmap, 1.0, -0.5, 35.0);
+        assert_height(&heightmap, -1.0, -0.5, 10.0);
+        assert_height(&heightmap, -0.5, -1.0, 10.0);
+        assert_height(&heightmap, 100.0, 0.0, 72.5);
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::voxel::chunk::Chunk;
+use crate::voxel::util::{CHUNK_SIZE, CHUNK_VOL};
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::pipeline::{ChunkNeighbourhood, Feature};
+use crate::worldgen::world::ColumnMap;
+
+/// Describes an ore and where its veins show up.
+#[derive(Debug, Clone)]
+pub struct OreDefinition {
+    pub name: String,
+    pub block: VoxelType,
+    /// The block veins grow through. Nothing else gets replaced.
+    pub host_block: VoxelType,
+    /// Veins only start between these world heights.
+    pub min_height: i32,
+    pub max_height: i32,
+    /// How many steps the random walk of a vein takes.
+    /// Steps sometimes go back over themselves, so veins end up a bit smaller.
+    pub vein_size: u32,
+    /// How many veins start in every chunk, on average. Can be below 1 for rare ores.
+    pub veins_per_chunk: f64,
+}
+
+/// All the ores the world generator places.
+/// Other plugins add their own through `App::register_ore`.
+#[derive(Resource, Clone)]
+pub struct OreRegistry {
+    ores: Vec<OreDefinition>,
+}
+
+impl Default for OreRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(OreDefinition {
+            name: "coal".into(),
+            block: VoxelType::COAL_ORE,
+            host_block: VoxelType::STONE,
+            min_height: -128,
+            max_height: 96,
+            vein_size: 14,
+            veins_per_chunk: 12.0,
+        });
+        registry.register(OreDefinition {
+            name: "iron".into(),
+            block: VoxelType::IRON_ORE,
+            host_block: VoxelType::STONE,
+            min_height: -192,
+            max_height: 32,
+            vein_size: 8,
+            veins_per_chunk: 6.0,
+        });
+        registry.register(OreDefinition {
+            name: "gold".into(),
+            block: VoxelType::GOLD_ORE,
+            host_block: VoxelType::STONE,
+            min_height: -256,
+            max_height: -48,
+            vein_size: 6,
+            veins_per_chunk: 1.5,
+        });
+        registry
+    }
+}
+
+impl OreRegistry {
+    /// A registry without any ores, for when the built-in ones aren't wanted.
+    pub fn empty() -> Self {
+        Self {
+            ores: Vec::new(),
+        }
+    }
+
+    /// Adds an ore. An ore with the same name as an existing one replaces it.
+    /// A `veins_per_chunk` that is negative, NaN or infinite is taken as 0.
+    pub fn register(&mut self, mut ore: OreDefinition) {
+        if !(ore.veins_per_chunk.is_finite() && ore.veins_per_chunk >= 0.0) {
+            warn!("Ore {} has {} veins per chunk, it won't be placed", ore.name, ore.veins_per_chunk);
+            ore.veins_per_chunk = 0.0;
+        }
+
+        if let Some(existing) = self.ores.iter_mut().find(|existing| existing.name == ore.name) {
+            *existing = ore;
+            return;
+        }
+
+        self.ores.push(ore);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &OreDefinition> {
+        self.ores.iter()
+    }
+}
+
+/// Lets plugins add ores while the app is being built.
+pub trait RegisterOre {
+    fn register_ore(&mut self, ore: OreDefinition) -> &mut Self;
+}
+
+impl RegisterOre for App {
+    fn register_ore(&mut self, ore: OreDefinition) -> &mut Self {
+        self.init_resource::<OreRegistry>();
+        self.world.resource_mut::<OreRegistry>().register(ore);
+        self
+    }
+}
+
+/// Grows ore veins through the ground with random walks.
+///
+/// Every chunk gets its own random generator, seeded with the world seed and
+/// its position, and starts its veins inside itself. Veins can wander off into
+/// the chunks around it. Where veins of different ores cross, the one that
+/// comes last in the registry wins, whichever chunk got decorated first.
+pub struct OreFeature {
+    seed: u32,
+    ores: Vec<OreDefinition>,
+}
+
+impl OreFeature {
+    pub fn new(seed: u32, ores: &OreRegistry) -> Self {
+        Self {
+            seed,
+            ores: ores.iter().cloned().collect(),
+        }
+    }
+
+    fn chunk_seed(&self, chunk_pos: IVec3) -> u64 {
+        // FNV-1a over the seed and the position, offset so it
+        // doesn't share its random numbers with the vegetation
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for value in [self.seed.wrapping_add(20) as i32, chunk_pos.x, chunk_pos.y, chunk_pos.z] {
+            hash ^= value as u32 as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    /// Whether an ore can go where `existing` is.
+    /// Ores that come later in the registry replace earlier ones growing through the same host.
+    fn can_replace(&self, index: usize, existing: VoxelType) -> bool {
+        let ore = &self.ores[index];
+
+        existing == ore.host_block || self.ores[..index].iter()
+            .any(|earlier| earlier.block == existing && earlier.host_block == ore.host_block)
+    }
+}
+
+impl Feature for OreFeature {
+    fn place(&self, chunk_pos: IVec3, _columns: &ColumnMap, neighbourhood: &mut ChunkNeighbourhood) {
+        let origin = neighbourhood.origin();
+        let mut rng = StdRng::seed_from_u64(self.chunk_seed(chunk_pos));
+
+        for (index, ore) in self.ores.iter().enumerate() {
+            // The fraction is a chance of one more vein
+            let extra = rng.gen_bool(ore.veins_per_chunk.fract());
+            let veins = ore.veins_per_chunk as u32 + extra as u32;
+
+            for _ in 0..veins {
+                let start = origin + IVec3::new(
+                    rng.gen_range(0..CHUNK_SIZE),
+                    rng.gen_range(0..CHUNK_SIZE),
+                    rng.gen_range(0..CHUNK_SIZE),
+                );
+                let in_range = (ore.min_height..=ore.max_height).contains(&start.y);
+
+                let mut world_pos = start;
+                for _ in 0..ore.vein_size {
+                    // Keep walking even when out of range, so every vein uses up the same random numbers
+                    if in_range && neighbourhood.get(world_pos).is_some_and(|existing| self.can_replace(index, existing)) {
+                        neighbourhood.set(world_pos, ore.block);
+                    }
+
+                    let step = match rng.gen_range(0..6) {
+                        0 => IVec3::X,
+                        1 => IVec3::NEG_X,
+                        2 => IVec3::Y,
+                        3 => IVec3::NEG_Y,
+                        4 => IVec3::Z,
+                        _ => IVec3::NEG_Z,
+                    };
+                    world_pos += step;
+                }
+            }
+        }
+    }
+}
+
+/// How much of each ore there is in a bunch of chunks, for tuning the ore table.
+#[derive(Debug, Clone, Default)]
+pub struct OreStats {
+    /// Voxels counted, ores and everything else.
+    pub voxels: u64,
+    pub ores: Vec<OreCount>,
+}
+
+/// How much there is of a single ore.
+#[derive(Debug, Clone)]
+pub struct OreCount {
+    pub name: String,
+    pub block: VoxelType,
+    pub count: u64,
+    /// The lowest and highest world heights it was found at.
+    pub min_height: Option<i32>,
+    pub max_height: Option<i32>,
+    height_sum: i64,
+}
+
+impl OreCount {
+    /// The average world height it was found at.
+    pub fn mean_height(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.height_sum as f64 / self.count as f64)
+    }
+}
+
+impl OreStats {
+    /// Counts every ore of the registry in the chunks.
+    pub fn collect<'a>(ores: &OreRegistry, chunks: impl Iterator<Item = &'a Chunk>) -> Self {
+        let mut stats = Self {
+            voxels: 0,
+            ores: ores.iter()
+                .map(|ore| OreCount {
+                    name: ore.name.clone(),
+                    block: ore.block,
+                    count: 0,
+                    min_height: None,
+                    max_height: None,
+                    height_sum: 0,
+                })
+                .collect(),
+        };
+
+        for chunk in chunks {
+            stats.voxels += CHUNK_VOL as u64;
+            if chunk.single_type().is_some() {
+                // Veins are far too small to fill a whole chunk
+                continue;
+            }
+
+            let world_y = chunk.position.y * CHUNK_SIZE;
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        let voxel_type = chunk.get(IVec3::new(x, y, z));
+                        let Some(ore) = stats.ores.iter_mut().find(|ore| ore.block == voxel_type) else {
+                            continue;
+                        };
+
+                        let wy = world_y + y;
+                        ore.count += 1;
+                        ore.height_sum += wy as i64;
+                        ore.min_height = Some(ore.min_height.map_or(wy, |min| min.min(wy)));
+                        ore.max_height = Some(ore.max_height.map_or(wy, |max| max.max(wy)));
+                    }
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+impl fmt::Display for OreStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} voxels counted", self.voxels)?;
+        for ore in self.ores.iter() {
+            let per_mille = ore.count as f64 / self.voxels.max(1) as f64 * 1000.0;
+            write!(f, "{}: {} ({per_mille:.3}‰)", ore.name, ore.count)?;
+            if let (Some(min), Some(max), Some(mean)) = (ore.min_height, ore.max_height, ore.mean_height()) {
+                write!(f, ", heights {min} to {max}, mean {mean:.1}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+use bevy::math::IVec3;
+use crate::voxel::chunk::Chunk;
+use crate::voxel::util::{CHUNK_SIZE, chunk_position, local_position};
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::biome::BiomeRegistry;
+use crate::worldgen::generator::{ChunkGenerator, ColumnSample};
+use crate::worldgen::world::{ColumnMap, TerrainGenerator};
+
+/// How far along the generation stages a chunk is.
+/// Each status means that stage is done, so a chunk at `Surface`
+/// has its terrain, caves and surface, but no features yet.
+///
+/// A stage only runs once all 26 neighbours have reached the stage
+/// before it. That way features can write into the chunks around them,
+/// and those chunks are never further along than they should be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChunkStatus {
+    Empty,
+    Terrain,
+    Carving,
+    Surface,
+    Features,
+    Lighting,
+}
+
+impl ChunkStatus {
+    /// The last stage. Chunks that got this far are moved into the world.
+    pub const FINISHED: ChunkStatus = ChunkStatus::Lighting;
+
+    const ALL: [ChunkStatus; 6] = [
+        ChunkStatus::Empty,
+        ChunkStatus::Terrain,
+        ChunkStatus::Carving,
+        ChunkStatus::Surface,
+        ChunkStatus::Features,
+        ChunkStatus::Lighting,
+    ];
+
+    pub fn next(self) -> Option<ChunkStatus> {
+        ChunkStatus::ALL.get(self as usize + 1).copied()
+    }
+
+    pub fn previous(self) -> Option<ChunkStatus> {
+        (self as usize).checked_sub(1).map(|index| ChunkStatus::ALL[index])
+    }
+
+    /// Whether the stage reads or writes the chunks around it.
+    /// Those chunks are locked while it runs.
+    pub fn touches_neighbours(self) -> bool {
+        matches!(self, ChunkStatus::Features | ChunkStatus::Lighting)
+    }
+
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<ChunkStatus> {
+        ChunkStatus::ALL.get(id as usize).copied()
+    }
+}
+
+/// A chunk that's still working its way through the stages.
+#[derive(Clone)]
+pub struct ProtoChunk {
+    pub chunk: Arc<Chunk>,
+    pub status: ChunkStatus,
+    /// The columns, kept around between stages. Missing for chunks loaded from disk.
+    pub columns: Option<Arc<ColumnMap>>,
+}
+
+impl ProtoChunk {
+    pub fn new(chunk: Chunk, status: ChunkStatus) -> Self {
+        Self {
+            chunk: Arc::new(chunk),
+            status,
+            columns: None,
+        }
+    }
+}
+
+/// Something placed into the world during the features stage, like a tree.
+///
+/// `place` is called once for every chunk and may write into any of the chunks
+/// around it. To come out the same no matter which chunk is decorated first,
+/// a feature has to be deterministic (seed it with the chunk position), mustn't
+/// look at what other features placed, and should only replace blocks by fixed
+/// rules, like leaves only going into AIR. Placing it twice should change nothing.
+pub trait Feature: Send + Sync {
+    /// * `chunk_pos`: The chunk being decorated.
+    /// * `columns`: The columns of that chunk.
+    /// * `neighbourhood`: The chunk and its neighbours, to read from and write into.
+    fn place(&self, chunk_pos: IVec3, columns: &ColumnMap, neighbourhood: &mut ChunkNeighbourhood);
+}
+
+/// A chunk and the 26 around it, addressed by world position.
+/// Missing chunks read as nothing and can't be written to, and neither
+/// can finished chunks, which are already in the world.
+pub struct ChunkNeighbourhood {
+    center: IVec3,
+    chunks: Vec<Option<Arc<Chunk>>>,
+    writable: [bool; 27],
+    modified: [bool; 27],
+}
+
+impl ChunkNeighbourhood {
+    pub fn new(center: IVec3) -> Self {
+        Self {
+            center,
+            chunks: vec![None; 27],
+            writable: [false; 27],
+            modified: [false; 27],
+        }
+    }
+
+    pub fn insert(&mut self, chunk: Arc<Chunk>, writable: bool) {
+        if let Some(slot) = self.slot(chunk.position) {
+            self.writable[slot] = writable;
+            self.chunks[slot] = Some(chunk);
+        }
+    }
+
+    fn slot(&self, chunk_pos: IVec3) -> Option<usize> {
+        let offset = chunk_pos - self.center + IVec3::ONE;
+        if offset.cmplt(IVec3::ZERO).any() || offset.cmpgt(IVec3::splat(2)).any() {
+            return None;
+        }
+
+        Some((offset.x + offset.y * 3 + offset.z * 9) as usize)
+    }
+
+    /// The VoxelType at a world position, if its chunk is there.
+    pub fn get(&self, world_pos: IVec3) -> Option<VoxelType> {
+        let slot = self.slot(chunk_position(world_pos))?;
+        self.chunks[slot].as_ref().map(|chunk| chunk.get(local_position(world_pos)))
+    }
+
+    /// Changes the voxel at a world position.
+    /// Returns false if its chunk is missing or can't be written to.
+    pub fn set(&mut self, world_pos: IVec3, voxel_type: VoxelType) -> bool {
+        let Some(slot) = self.slot(chunk_position(world_pos)) else {
+            return false;
+        };
+        let Some(chunk) = self.chunks[slot].as_mut() else {
+            return false;
+        };
+        if !self.writable[slot] {
+            return false;
+        }
+
+        Arc::make_mut(chunk).set(local_position(world_pos), voxel_type);
+        self.modified[slot] = true;
+        true
+    }
+
+    /// The world position of the lowest corner of the center chunk.
+    pub fn origin(&self) -> IVec3 {
+        self.center * CHUNK_SIZE
+    }
+
+    /// Every chunk that was written to.
+    fn into_modified(self) -> impl Iterator<Item = Arc<Chunk>> {
+        self.chunks.into_iter()
+            .zip(self.modified)
+            .filter_map(|(chunk, modified)| chunk.filter(|_| modified))
+    }
+}
+
+/// Everything a stage task needs to move a chunk one stage further.
+pub struct StageInput {
+    pub chunk: ProtoChunk,
+    /// The chunk and its neighbours, for the stages that touch neighbours.
+    pub neighbourhood: Option<ChunkNeighbourhood>,
+}
+
+/// What a stage task comes back with.
+pub struct StageOutput {
+    pub position: IVec3,
+    pub status: ChunkStatus,
+    pub chunk: Arc<Chunk>,
+    /// The columns, for generators that have them.
+    pub columns: Option<Arc<ColumnMap>>,
+    /// Neighbours that were written to.
+    pub neighbours: Vec<Arc<Chunk>>,
+}
+
+impl ChunkGenerator for TerrainGenerator {
+    fn run_stage(&self, input: StageInput) -> StageOutput {
+        let StageInput { chunk: proto, neighbourhood } = input;
+        let position = proto.chunk.position;
+        let status = proto.status.next().unwrap_or(ChunkStatus::FINISHED);
+        let columns = proto.columns.unwrap_or_else(|| Arc::new(self.columns(position)));
+        let mut chunk = proto.chunk;
+
+        let mut neighbours = Vec::new();
+        match status {
+            ChunkStatus::Empty => {}
+            ChunkStatus::Terrain => self.shape_terrain(Arc::make_mut(&mut chunk), &columns),
+            ChunkStatus::Carving => self.carve_caves(Arc::make_mut(&mut chunk), &columns),
+            ChunkStatus::Surface => {
+                self.surface.apply(Arc::make_mut(&mut chunk), &self.biomes.registry, |x, z| columns.get(x, z));
+            }
+            ChunkStatus::Features => {
+                let mut neighbourhood = neighbourhood.expect("the features stage needs the neighbours");
+                for feature in self.features.iter() {
+                    feature.place(position, &columns, &mut neighbourhood);
+                }
+
+                for modified in neighbourhood.into_modified() {
+                    if modified.position == position {
+                        chunk = modified;
+                    } else {
+                        neighbours.push(modified);
+                    }
+                }
+            }
+            // Light depends on the blocks, which generators don't know about.
+            // The task lights the chunk once this is done, see `light_chunk`.
+            ChunkStatus::Lighting => {}
+        }
+
+        if status == ChunkStatus::FINISHED {
+            Arc::make_mut(&mut chunk).compact();
+        }
+
+        StageOutput {
+            position,
+            status,
+            chunk,
+            columns: Some(columns),
+            neighbours,
+        }
+    }
+
+    fn sample_column(&self, wx: f64, wz: f64) -> Option<ColumnSample> {
+        let column = self.column(wx, wz);
+        Some(ColumnSample {
+            height: column.height,
+            water_level: Some(column.biome.water_level),
+            biome: Some(column.biome.dominant),
+        })
+    }
+
+    fn biomes(&self) -> Option<&BiomeRegistry> {
+        Some(&self.biomes.registry)
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use bevy::math::IVec3;
+use crate::voxel::chunk::Chunk;
+use crate::voxel::liquid::MAX_FLOW_LEVEL;
+use crate::voxel::util::{CHUNK_SIZE, CHUNK_VOL};
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::pipeline::ChunkStatus;
+
+/// Amount of chunks along each axis of a region.
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOL: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+/// Bump this whenever the layout of a region file changes.
+/// Versions 1 to 4 came and went while the format was still settling,
+/// they aren't upgraded anymore and get rejected like any other unknown version.
+pub const REGION_VERSION: u32 = 5;
+const REGION_MAGIC: &[u8; 4] = b"AVRG";
+/// Magic, version, then an offset and a length for every chunk in the region.
+const HEADER_SIZE: usize = 8 + REGION_VOL * 8;
+
+/// Chunk is a single VoxelType, followed by its id as a u16.
+const CHUNK_SINGLE: u8 = 0;
+/// Chunk is run-length encoded as (id: u16, run: u16) pairs in voxel index order.
+const CHUNK_RUNS: u8 = 1;
+/// Liquid levels come after the voxels, run-length encoded as (level: u8, run: u16)
+/// pairs in voxel index order.
+const LIQUID_RUN_SIZE: usize = 3;
+
+/// Saves and loads chunks to region files inside a directory.
+///
+/// Every region file holds up to REGION_SIZE³ chunks. It starts with a header
+/// containing the magic bytes, the format version and an offset table that
+/// says where each chunk's data starts and how long it is. A chunk that was
+/// never saved has an offset and length of 0. Each chunk is compressed on its own,
+/// after a byte with how far along the generation stages it is.
+/// All numbers are little endian.
+pub struct RegionStorage {
+    pub directory: PathBuf,
+    /// Chunks that are on their way to disk, see `hold`.
+    held: Mutex<HashMap<IVec3, (Arc<Chunk>, ChunkStatus)>>,
+    /// Taken while region files are written. Two saves into the same region would
+    /// otherwise both read it, and the last one to write would drop the other's chunks.
+    writing: Mutex<()>,
+}
+
+impl RegionStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            held: Mutex::new(HashMap::new()),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Loads a chunk and its status from its region file.
+    /// Returns `None` if the chunk has never been saved.
+    pub fn load_chunk(&self, chunk_pos: IVec3) -> io::Result<Option<(Chunk, ChunkStatus)>> {
+        if let Some((chunk, status)) = self.held.lock().unwrap().get(&chunk_pos) {
+            return Ok(Some((chunk.as_ref().clone(), *status)));
+        }
+
+        let data = match fs::read(self.region_path(region_position(chunk_pos))) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut entries = read_region(&data)?;
+        match entries[region_index(chunk_pos)].take() {
+            Some(chunk_data) => decode_chunk(chunk_pos, &chunk_data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Saves chunks along with their status to their region files.
+    /// Chunks already in the files are kept, unless they get overwritten.
+    pub fn save_chunks<'a>(&self, chunks: impl IntoIterator<Item = (&'a Chunk, ChunkStatus)>) -> io::Result<()> {
+        let _writing = self.writing.lock().unwrap();
+        self.write_chunks(chunks)
+    }
+
+    /// Keeps chunks around until `save_held` has written them, so loading one
+    /// in the meantime doesn't bring back whatever was saved before.
+    pub fn hold(&self, chunks: &[(Arc<Chunk>, ChunkStatus)]) {
+        let mut held = self.held.lock().unwrap();
+        for (chunk, status) in chunks {
+            held.insert(chunk.position, (chunk.clone(), *status));
+        }
+    }
+
+    /// Saves chunks passed to `hold` and stops holding them, even if saving fails.
+    /// Chunks that were held again since, with newer changes, are left to the save that held them.
+    pub fn save_held(&self, chunks: &[(Arc<Chunk>, ChunkStatus)]) -> io::Result<()> {
+        let is_held = |held: &HashMap<IVec3, (Arc<Chunk>, ChunkStatus)>, chunk: &Arc<Chunk>| {
+            held.get(&chunk.position).is_some_and(|(held_chunk, _)| Arc::ptr_eq(held_chunk, chunk))
+        };
+
+        let _writing = self.writing.lock().unwrap();
+        let still_held: Vec<(&Chunk, ChunkStatus)> = {
+            let held = self.held.lock().unwrap();
+            chunks.iter()
+                .filter(|(chunk, _)| is_held(&held, chunk))
+                .map(|(chunk, status)| (chunk.as_ref(), *status))
+                .collect()
+        };
+        let result = self.write_chunks(still_held);
+
+        let mut held = self.held.lock().unwrap();
+        for (chunk, _) in chunks {
+            if is_held(&held, chunk) {
+                held.remove(&chunk.position);
+            }
+        }
+
+        result
+    }
+
+    /// `save_chunks` without taking the `writing` lock.
+    fn write_chunks<'a>(&self, chunks: impl IntoIterator<Item = (&'a Chunk, ChunkStatus)>) -> io::Result<()> {
+        let mut regions: HashMap<IVec3, Vec<(&Chunk, ChunkStatus)>> = HashMap::new();
+        for (chunk, status) in chunks {
+            regions.entry(region_position(chunk.position)).or_default().push((chunk, status));
+        }
+
+        if regions.is_empty() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.directory)?;
+
+        for (region_pos, chunks) in regions {
+            let path = self.region_path(region_pos);
+            let mut entries = match fs::read(&path) {
+                Ok(data) => read_region(&data)?,
+                Err(err) if err.kind() == ErrorKind::NotFound => vec![None; REGION_VOL],
+                Err(err) => return Err(err),
+            };
+
+            for (chunk, status) in chunks {
+                entries[region_index(chunk.position)] = Some(encode_chunk(chunk, status));
+            }
+
+            // Write to a temporary file first, so a crash or a
+            // generation task reading the file never sees half of it.
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, write_region(&entries))?;
+            fs::rename(temp_path, path)?;
+        }
+
+        Ok(())
+    }
+
+    fn region_path(&self, region_pos: IVec3) -> PathBuf {
+        self.directory.join(format!("r.{}.{}.{}.avr", region_pos.x, region_pos.y, region_pos.z))
+    }
+}
+
+/// The region a chunk belongs to.
+fn region_position(chunk_pos: IVec3) -> IVec3 {
+    chunk_pos.div_euclid(IVec3::splat(REGION_SIZE))
+}
+
+/// Where in the offset table a chunk is.
+fn region_index(chunk_pos: IVec3) -> usize {
+    let local = chunk_pos.rem_euclid(IVec3::splat(REGION_SIZE));
+    (local.x + local.z * REGION_SIZE + local.y * REGION_SIZE * REGION_SIZE) as usize
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| invalid_data("region file is truncated"))
+}
+
+/// Splits a region file into the data of each chunk.
+fn read_region(data: &[u8]) -> io::Result<Vec<Option<Vec<u8>>>> {
+    if data.get(0..4) != Some(REGION_MAGIC.as_slice()) {
+        return Err(invalid_data("not a region file"));
+    }
+
+    let version = read_u32(data, 4)?;
+    if version != REGION_VERSION {
+        return Err(invalid_data(&format!("unsupported region version {version}")));
+    }
+
+    let mut entries = Vec::with_capacity(REGION_VOL);
+    for i in 0..REGION_VOL {
+        let offset = read_u32(data, 8 + i * 8)? as usize;
+        let length = read_u32(data, 12 + i * 8)? as usize;
+
+        if length == 0 {
+            entries.push(None);
+            continue;
+        }
+
+        let chunk_data = data.get(offset..offset + length)
+            .ok_or_else(|| invalid_data("chunk data is out of bounds"))?;
+        entries.push(Some(chunk_data.to_vec()));
+    }
+
+    Ok(entries)
+}
+
+fn write_region(entries: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(REGION_MAGIC);
+    header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+
+    let mut body = Vec::new();
+    for entry in entries {
+        let (offset, length) = match entry {
+            Some(chunk_data) => {
+                let offset = HEADER_SIZE + body.len();
+                body.extend_from_slice(chunk_data);
+                (offset as u32, chunk_data.len() as u32)
+            }
+            None => (0, 0),
+        };
+
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+    }
+
+    header.extend(body);
+    header
+}
+
+/// Calls `f` for every local position in the chunk, in voxel index order.
+fn for_each_position(mut f: impl FnMut(IVec3)) {
+    for y in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                f(IVec3::new(x, y, z));
+            }
+        }
+    }
+}
+
+fn encode_chunk(chunk: &Chunk, status: ChunkStatus) -> Vec<u8> {
+    let mut data = vec![status.id()];
+    if let Some(voxel_type) = chunk.single_type() {
+        data.push(CHUNK_SINGLE);
+        data.extend_from_slice(&voxel_type.0.to_le_bytes());
+    } else {
+        data.push(CHUNK_RUNS);
+        encode_runs(&mut data, |local_pos| chunk.get(local_pos), |data, voxel_type| {
+            data.extend_from_slice(&voxel_type.0.to_le_bytes());
+        });
+    }
+
+    encode_runs(&mut data, |local_pos| chunk.liquid_level(local_pos), |data, level| data.push(level));
+    data
+}
+
+/// Run-length encodes a value of every voxel, with `write` putting down the value of each run
+/// before its length.
+fn encode_runs<T: PartialEq>(
+    data: &mut Vec<u8>,
+    value: impl Fn(IVec3) -> T,
+    write: impl Fn(&mut Vec<u8>, T),
+) {
+    let mut run: Option<(T, u16)> = None;
+    for_each_position(|local_pos| {
+        let next = value(local_pos);
+        run = match run.take() {
+            Some((run_value, length)) if run_value == next => Some((run_value, length + 1)),
+            Some((run_value, length)) => {
+                write(data, run_value);
+                data.extend_from_slice(&length.to_le_bytes());
+                Some((next, 1))
+            }
+            None => Some((next, 1)),
+        };
+    });
+
+    if let Some((run_value, length)) = run {
+        write(data, run_value);
+        data.extend_from_slice(&length.to_le_bytes());
+    }
+}
+
+fn decode_chunk(chunk_pos: IVec3, data: &[u8]) -> io::Result<(Chunk, ChunkStatus)> {
+    let status = data.first()
+        .and_then(|id| ChunkStatus::from_id(*id))
+        .ok_or_else(|| invalid_data("unknown chunk status"))?;
+    let data = &data[1..];
+
+    let (mut chunk, liquid_data) = match data.first() {
+        Some(&CHUNK_SINGLE) => {
+            let [_, low, high, liquid_data @ ..] = data else {
+                return Err(invalid_data("chunk data is truncated"));
+            };
+            (Chunk::filled(chunk_pos, VoxelType(u16::from_le_bytes([*low, *high]))), liquid_data)
+        }
+        Some(&CHUNK_RUNS) => {
+            let mut voxel_types = Vec::with_capacity(CHUNK_VOL as usize);
+            let mut runs = data[1..].chunks(4);
+            let mut runs_read = 0;
+            while voxel_types.len() < CHUNK_VOL as usize {
+                let Some([id_low, id_high, low, high]) = runs.next() else {
+                    return Err(invalid_data("chunk data is truncated"));
+                };
+                runs_read += 1;
+                let voxel_type = VoxelType(u16::from_le_bytes([*id_low, *id_high]));
+                let length = u16::from_le_bytes([*low, *high]) as usize;
+                voxel_types.extend(std::iter::repeat_n(voxel_type, length));
+            }
+
+            if voxel_types.len() != CHUNK_VOL as usize {
+                return Err(invalid_data("chunk has the wrong amount of voxels"));
+            }
+
+            let mut chunk = Chunk::new(chunk_pos);
+            let mut voxel_types = voxel_types.into_iter();
+            for_each_position(|local_pos| {
+                if let Some(voxel_type) = voxel_types.next() {
+                    chunk.set(local_pos, voxel_type);
+                }
+            });
+            (chunk, &data[1 + runs_read * 4..])
+        }
+        _ => return Err(invalid_data("unknown chunk encoding")),
+    };
+
+    let mut levels = Vec::with_capacity(CHUNK_VOL as usize);
+    for run in liquid_data.chunks(LIQUID_RUN_SIZE) {
+        let [level, low, high] = run else {
+            return Err(invalid_data("liquid levels are truncated"));
+        };
+        if *level > MAX_FLOW_LEVEL {
+            return Err(invalid_data("liquid level is out of range"));
+        }
+        levels.extend(std::iter::repeat_n(*level, u16::from_le_bytes([*low, *high]) as usize));
+    }
+
+    if levels.len() != CHUNK_VOL as usize {
+        return Err(invalid_data("chunk has the wrong amount of liquid levels"));
+    }
+
+    let mut levels = levels.into_iter();
+    for_each_position(|local_pos| {
+        if let Some(level) = levels.next() {
+            chunk.set_liquid_level(local_pos, level);
+        }
+    });
+
+    chunk.compact();
+    Ok((chunk, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use bevy::math::IVec3;
+    use crate::voxel::chunk::Chunk;
+    use crate::voxel::voxel::VoxelType;
+    use crate::worldgen::config::WorldGenConfig;
+    use crate::worldgen::pipeline::ChunkStatus;
+    use super::{RegionStorage, decode_chunk, encode_chunk, for_each_position};
+
+    fn assert_same_chunk(expected: &Chunk, actual: &Chunk) {
+        assert_eq!(expected.position, actual.position);
+        for_each_position(|local_pos| {
+            assert_eq!(expected.get(local_pos), actual.get(local_pos), "voxel at {local_pos}");
+            assert_eq!(expected.liquid_level(local_pos), actual.liquid_level(local_pos), "liquid level at {local_pos}");
+        });
+    }
+
+    /// A few layers of different blocks, with a pool of flowing water on top.
+    fn paletted_chunk(chunk_pos: IVec3) -> Chunk {
+        let mut chunk = Chunk::new(chunk_pos);
+        for_each_position(|local_pos| {
+            let voxel_type = match local_pos.y {
+                y if y < 10 => VoxelType::STONE,
+                y if y < 14 && (local_pos.x + local_pos.z) % 5 == 0 => VoxelType::COAL_ORE,
+                y if y < 14 => VoxelType::DIRT,
+                14 => VoxelType::GRASS,
+                _ => VoxelType::AIR,
+            };
+            chunk.set(local_pos, voxel_type);
+        });
+
+        for x in 4..12 {
+            for z in 4..12 {
+                let local_pos = IVec3::new(x, 15, z);
+                chunk.set(local_pos, VoxelType::WATER);
+                chunk.set_liquid_level(local_pos, ((x + z) % 8) as u8);
+            }
+        }
+        chunk.compact();
+        chunk
+    }
+
+    /// A directory of its own for every test, since they run at the same time.
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("aravoxel-region-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn single_type_round_trip() {
+        let chunk = Chunk::filled(IVec3::new(3, -2, 7), VoxelType::STONE);
+        let (decoded, status) = decode_chunk(chunk.position, &encode_chunk(&chunk, ChunkStatus::FINISHED)).unwrap();
+        assert_eq!(status, ChunkStatus::FINISHED);
+        assert_eq!(decoded.single_type(), Some(VoxelType::STONE));
+        assert_same_chunk(&chunk, &decoded);
+    }
+
+    #[test]
+    fn paletted_round_trip() {
+        let chunk = paletted_chunk(IVec3::new(-1, 0, 2));
+        let (decoded, status) = decode_chunk(chunk.position, &encode_chunk(&chunk, ChunkStatus::Surface)).unwrap();
+        assert_eq!(status, ChunkStatus::Surface);
+        assert_same_chunk(&chunk, &decoded);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let storage = RegionStorage::new(test_directory("save-and-load"));
+        // Two in the same region, one in the region next to it
+        let chunks = [
+            paletted_chunk(IVec3::new(0, 0, 0)),
+            Chunk::filled(IVec3::new(1, 2, 3), VoxelType::DIRT),
+            paletted_chunk(IVec3::new(-1, 0, 0)),
+        ];
+        storage.save_chunks(chunks.iter().map(|chunk| (chunk, ChunkStatus::FINISHED))).unwrap();
+
+        // Saving more into a region keeps what was already in it
+        let extra = Chunk::filled(IVec3::new(2, 0, 0), VoxelType::SAND);
+        storage.save_chunks([(&extra, ChunkStatus::Terrain)]).unwrap();
+
+        for chunk in chunks.iter() {
+            let (loaded, status) = storage.load_chunk(chunk.position).unwrap().expect("chunk was saved");
+            assert_eq!(status, ChunkStatus::FINISHED);
+            assert_same_chunk(chunk, &loaded);
+        }
+        let (loaded, status) = storage.load_chunk(extra.position).unwrap().expect("chunk was saved");
+        assert_eq!(status, ChunkStatus::Terrain);
+        assert_same_chunk(&extra, &loaded);
+
+        // Never saved, in a region that exists and one that doesn't
+        assert!(storage.load_chunk(IVec3::new(0, 1, 0)).unwrap().is_none());
+        assert!(storage.load_chunk(IVec3::new(0, 100, 0)).unwrap().is_none());
+
+        fs::remove_dir_all(&storage.directory).unwrap();
+    }
+
+    #[test]
+    fn other_worlds_do_not_share_saves() {
+        let saves = test_directory("worlds");
+        let config = WorldGenConfig::default();
+        let storage = RegionStorage::new(saves.join(config.save_name()));
+        let chunk = Chunk::filled(IVec3::ZERO, VoxelType::DIRT);
+        storage.save_chunks([(&chunk, ChunkStatus::FINISHED)]).unwrap();
+
+        let other_seed = WorldGenConfig { seed: config.seed + 1, ..config.clone() };
+        let other_preset = WorldGenConfig::preset("amplified").unwrap();
+        let other_generator = WorldGenConfig { generator: "superflat".into(), ..config.clone() };
+        let other_settings = WorldGenConfig { amplitude: config.amplitude * 2.0, ..config.clone() };
+        for other in [other_seed, other_preset, other_generator, other_settings] {
+            let other_storage = RegionStorage::new(saves.join(other.save_name()));
+            assert!(other_storage.load_chunk(IVec3::ZERO).unwrap().is_none(), "{} read the chunks of {}", other.save_name(), config.save_name());
+        }
+
+        // The same config finds them again
+        let same_storage = RegionStorage::new(saves.join(WorldGenConfig::default().save_name()));
+        assert!(same_storage.load_chunk(IVec3::ZERO).unwrap().is_some());
+
+        fs::remove_dir_all(&saves).unwrap();
+    }
+
+    #[test]
+    fn held_chunks_load_before_they_are_saved() {
+        let storage = RegionStorage::new(test_directory("held"));
+        let old = [(Arc::new(Chunk::filled(IVec3::ZERO, VoxelType::DIRT)), ChunkStatus::FINISHED)];
+        let newer = [(Arc::new(paletted_chunk(IVec3::ZERO)), ChunkStatus::FINISHED)];
+
+        storage.hold(&old);
+        let (loaded, _) = storage.load_chunk(IVec3::ZERO).unwrap().expect("chunk is held");
+        assert_same_chunk(&old[0].0, &loaded);
+
+        // Held again with newer changes before the first save got to it
+        storage.hold(&newer);
+        storage.save_held(&old).unwrap();
+        let (loaded, _) = storage.load_chunk(IVec3::ZERO).unwrap().expect("chunk is held");
+        assert_same_chunk(&newer[0].0, &loaded);
+        assert!(!storage.directory.exists(), "the old chunk was written");
+
+        storage.save_held(&newer).unwrap();
+        assert!(storage.held.lock().unwrap().is_empty());
+        let (loaded, _) = storage.load_chunk(IVec3::ZERO).unwrap().expect("chunk was saved");
+        assert_same_chunk(&newer[0].0, &loaded);
+
+        fs::remove_dir_all(&storage.directory).unwrap();
+    }
+
+    #[test]
+    fn truncated_chunks_are_errors() {
+        for chunk in [Chunk::filled(IVec3::ZERO, VoxelType::STONE), paletted_chunk(IVec3::ZERO)] {
+            let data = encode_chunk(&chunk, ChunkStatus::FINISHED);
+            for length in 0..data.len() {
+                assert!(decode_chunk(IVec3::ZERO, &data[..length]).is_err(), "{length} of {} bytes decoded", data.len());
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_chunks_are_errors() {
+        let data = encode_chunk(&paletted_chunk(IVec3::ZERO), ChunkStatus::FINISHED);
+
+        let mut unknown_status = data.clone();
+        unknown_status[0] = 200;
+        assert!(decode_chunk(IVec3::ZERO, &unknown_status).is_err());
+
+        let mut unknown_encoding = data.clone();
+        unknown_encoding[1] = 200;
+        assert!(decode_chunk(IVec3::ZERO, &unknown_encoding).is_err());
+
+        // The last run of liquid levels, made too long and then out of range
+        let level_run = data.len() - 3;
+        let mut too_long = data.clone();
+        too_long[level_run + 1..].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(decode_chunk(IVec3::ZERO, &too_long).is_err());
+
+        let mut bad_level = data.clone();
+        bad_level[level_run] = 200;
+        assert!(decode_chunk(IVec3::ZERO, &bad_level).is_err());
+
+        let mut extra = data.clone();
+        extra.push(0);
+        assert!(decode_chunk(IVec3::ZERO, &extra).is_err());
+    }
+
+    #[test]
+    fn corrupt_region_files_are_errors() {
+        let storage = RegionStorage::new(test_directory("corrupt"));
+        let chunk = paletted_chunk(IVec3::ZERO);
+        storage.save_chunks([(&chunk, ChunkStatus::FINISHED)]).unwrap();
+
+        let path = storage.region_path(IVec3::ZERO);
+        let data = fs::read(&path).unwrap();
+
+        // Cut off in the header, and in the middle of the chunk
+        for length in [0, 3, 6, 100, data.len() - 1] {
+            fs::write(&path, &data[..length]).unwrap();
+            assert!(storage.load_chunk(chunk.position).is_err(), "{length} of {} bytes loaded", data.len());
+            assert!(storage.save_chunks([(&chunk, ChunkStatus::FINISHED)]).is_err());
+        }
+
+        let mut wrong_magic = data.clone();
+        wrong_magic[0] = b'X';
+        fs::write(&path, &wrong_magic).unwrap();
+        assert!(storage.load_chunk(chunk.position).is_err());
+
+        // Newer versions, and the old ones that aren't upgraded anymore
+        for version in [1, 4, 99] {
+            let mut wrong_version = data.clone();
+            wrong_version[4] = version;
+            fs::write(&path, &wrong_version).unwrap();
+            assert!(storage.load_chunk(chunk.position).is_err(), "version {version} loaded");
+        }
+
+        fs::remove_dir_all(&storage.directory).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::math::IVec3;
+use crate::voxel::chunk::Chunk;
+use crate::voxel::util::CHUNK_SIZE;
+use crate::voxel::voxel::VoxelType;
+use crate::worldgen::biome::BiomeRegistry;
+use crate::worldgen::world::Column;
+
+/// Tunables for the surface pass.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSettings {
+    /// How many voxels of subsurface block go under the surface block.
+    pub subsurface_depth: i32,
+    /// Surfaces from this far below the water level...
+    pub beach_depth: i32,
+    /// ...up to this far above it turn into sand.
+    pub beach_height: i32,
+}
+
+impl Default for SurfaceSettings {
+    fn default() -> Self {
+        Self {
+            subsurface_depth: 3,
+            beach_depth: 2,
+            beach_height: 1,
+        }
+    }
+}
+
+/// Dresses up the bare stone terrain: the top voxel of each column gets its
+/// biome's surface block, the few under it the subsurface block, and anything
+/// close to the water level becomes a sand beach.
+///
+/// Depth is measured from the height of the column rather than from what's in
+/// the chunk, so a column whose surface sits in the chunk above still gets
+/// the right subsurface blocks at the top of this one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SurfacePass {
+    pub settings: SurfaceSettings,
+}
+
+impl SurfacePass {
+    /// Decorates every column of a chunk. Only STONE gets replaced,
+    /// so whatever the caves carved out stays carved out.
+    ///
+    /// * `column_at`: The Column at a local x and z.
+    pub fn apply(&self, chunk: &mut Chunk, biomes: &BiomeRegistry, column_at: impl Fn(i32, i32) -> Column) {
+        let chunk_y = chunk.position.y * CHUNK_SIZE;
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let column = column_at(x, z);
+                let biome = biomes.get(column.biome.dominant);
+
+                // The top voxel is the highest one at or below the height
+                let surface_y = column.height.floor() as i32;
+                let water_level = column.biome.water_level.floor() as i32;
+
+                let beach = (water_level - self.settings.beach_depth..=water_level + self.settings.beach_height)
+                    .contains(&surface_y);
+                let (top_block, lower_block) = if beach {
+                    (VoxelType::SAND, VoxelType::SAND)
+                } else if column.height < column.biome.water_level {
+                    // Grass doesn't grow underwater
+                    (biome.subsurface_block, biome.subsurface_block)
+                } else {
+                    (biome.surface_block, biome.subsurface_block)
+                };
+
+                let lowest = (surface_y - self.settings.subsurface_depth).max(chunk_y);
+                let highest = surface_y.min(chunk_y + CHUNK_SIZE - 1);
+                for wy in lowest..=highest {
+                    let local_pos = IVec3::new(x, wy - chunk_y, z);
+                    if chunk.get(local_pos) != VoxelType::STONE {
+                        continue;
+                    }
+
+                    let voxel_type = if wy == surface_y { top_block } else { lower_block };
+                    chunk.set(local_pos, voxel_type);
+                }
+            }
+        }
+    }
+}
@@ -1,87 +1,373 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
 use bevy::prelude::*;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
 use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
 use splines::Spline;
-use crate::voxel::chunk::Chunk;
-use crate::voxel::util::{CHUNK_SIZE, CHUNK_SIZE_F64, voxel_index};
+use crate::voxel::chunk::{Chunk, ChunkMap};
+use crate::voxel::light::update_light;
+use crate::voxel::registry::BlockRegistry;
+use crate::voxel::util::{CHUNK_SIZE, chunk_position, chunks_touching, local_position};
 use crate::voxel::voxel::VoxelType;
+use crate::worldgen::biome::{BiomeMap, BiomeRegistry, ColumnBiome, linear_spline};
+use crate::worldgen::caves::{CaveCarver, CaveSettings};
+use crate::worldgen::config::WorldGenConfig;
+use crate::worldgen::features::VegetationFeature;
+use crate::worldgen::generator::ChunkGenerator;
+use crate::worldgen::heightmap::Heightmap;
+use crate::worldgen::ores::{OreFeature, OreRegistry};
+use crate::worldgen::pipeline::{ChunkStatus, Feature, ProtoChunk};
+use crate::worldgen::region::RegionStorage;
+use crate::worldgen::surface::SurfacePass;
+
+/// Every world gets a directory in here, see `WorldGenConfig::save_name`.
+pub const SAVES_DIRECTORY: &str = "saves";
 
 #[derive(Resource)]
 pub struct VoxelWorld {
-    pub world_noise: Fbm<Perlin>,
-    pub verticality: Perlin,
-    pub spline_points: Spline<f64, f64>,
-    pub chunks: HashMap<IVec3, Chunk>,
-    //meshes: HashMap<IVec3, ChunkMesh>
+    /// Shared with the generation tasks, hence the Arc.
+    pub generator: Arc<dyn ChunkGenerator>,
+    /// Where chunks are saved to and loaded from. Also shared with the generation tasks.
+    pub region_storage: Arc<RegionStorage>,
+    /// Finished chunks.
+    pub chunks: ChunkMap,
+    /// Chunks that are still going through the generation stages.
+    pub proto_chunks: HashMap<IVec3, ProtoChunk>,
+    /// Loaded chunks that have changed since they were last saved.
+    /// Chunks that were only generated aren't in here, since generating them again
+    /// gives the same result. That stops being true once the player edits them,
+    /// or once features from a neighbouring chunk write into them.
+    pub unsaved: HashSet<IVec3>,
+    /// Saves of unloaded chunks that are still being written.
+    saving: Vec<Task<()>>,
 }
 
 impl Default for VoxelWorld {
     fn default() -> Self {
+        Self::for_config(&WorldGenConfig::default())
+    }
+}
+
+impl VoxelWorld {
+    /// An empty world that keeps its saves apart from those of worlds with another config.
+    /// The generator still has to be built from the config, see `ChunkGenerators`.
+    pub fn for_config(config: &WorldGenConfig) -> Self {
         Self {
-            world_noise: Fbm::<Perlin>::new(6346547).set_octaves(6).set_lacunarity(2.0).set_persistence(0.5),
-            verticality: Perlin::new(6346547),
-            spline_points: Spline::from_vec(vec![
-                splines::Key::new(-1., 0.3, splines::Interpolation::Linear),
-                splines::Key::new(-0.6, 0.35, splines::Interpolation::Linear),
-                splines::Key::new(-0.4, 1.0, splines::Interpolation::Linear),
-                splines::Key::new(-0.2, 1.0, splines::Interpolation::Linear),
-                splines::Key::new(0.0, 1.3, splines::Interpolation::Linear),
-                splines::Key::new(0.1, 1.4, splines::Interpolation::Linear),
-                splines::Key::new(0.2, 1.4, splines::Interpolation::Linear),
-                splines::Key::new(0.3, 1.4, splines::Interpolation::Linear),
-                splines::Key::new(0.4, 1.7, splines::Interpolation::Linear),
-                splines::Key::new(0.5, 2.0, splines::Interpolation::Linear),
-                splines::Key::new(0.6, 2.1, splines::Interpolation::Linear),
-                splines::Key::new(0.7, 3.0, splines::Interpolation::Linear),
-                splines::Key::new(1.1, 3.0, splines::Interpolation::default())
-            ]),
+            generator: Arc::new(TerrainGenerator::default()),
+            region_storage: Arc::new(RegionStorage::new(Path::new(SAVES_DIRECTORY).join(config.save_name()))),
             chunks: HashMap::new(),
-            //meshes: HashMap::new(),
+            proto_chunks: HashMap::new(),
+            unsaved: HashSet::new(),
+            saving: Vec::new(),
+        }
+    }
+
+    /// The VoxelType at a world position, if the chunk it's in is loaded.
+    pub fn get_voxel(&self, world_pos: IVec3) -> Option<VoxelType> {
+        self.chunks
+            .get(&chunk_position(world_pos))
+            .map(|chunk| chunk.get(local_position(world_pos)))
+    }
+
+    /// The liquid level at a world position, if the chunk it's in is loaded.
+    pub fn liquid_level(&self, world_pos: IVec3) -> Option<u8> {
+        self.chunks
+            .get(&chunk_position(world_pos))
+            .map(|chunk| chunk.liquid_level(local_position(world_pos)))
+    }
+
+    /// Changes the liquid level at a world position.
+    /// Returns the loaded chunks whose meshes are affected, the surface of the liquid
+    /// around it slopes with the level. Nothing changes if the chunk isn't loaded.
+    pub fn set_liquid_level(&mut self, world_pos: IVec3, level: u8) -> Vec<IVec3> {
+        let chunk_pos = chunk_position(world_pos);
+        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+            return Vec::new();
+        };
+
+        if chunk.liquid_level(local_position(world_pos)) == level {
+            return Vec::new();
+        }
+
+        Arc::make_mut(chunk).set_liquid_level(local_position(world_pos), level);
+        self.unsaved.insert(chunk_pos);
+        chunks_touching(world_pos).into_iter().filter(|pos| self.chunks.contains_key(pos)).collect()
+    }
+
+    /// Changes the voxel at a world position, and moves the light around to match.
+    /// Returns the loaded chunks whose meshes are affected: the chunk itself,
+    /// any neighbours when the voxel sits on its border, and whatever the light reached.
+    /// Nothing changes if the chunk isn't loaded.
+    pub fn set_voxel(&mut self, blocks: &BlockRegistry, world_pos: IVec3, voxel_type: VoxelType) -> Vec<IVec3> {
+        let chunk_pos = chunk_position(world_pos);
+        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+            return Vec::new();
+        };
+
+        // Tasks might still be holding on to the chunk, in which case it gets copied
+        Arc::make_mut(chunk).set(local_position(world_pos), voxel_type);
+        self.unsaved.insert(chunk_pos);
+
+        let mut dirty = update_light(&mut self.chunks, blocks, |update| update.voxel_changed(world_pos));
+
+        // Neighbours sample this voxel for culling and AO when it's on the border
+        dirty.extend(chunks_touching(world_pos).into_iter().filter(|pos| self.chunks.contains_key(pos)));
+        dirty.into_iter().collect()
+    }
+
+    /// How far along the generation stages a loaded chunk is.
+    pub fn chunk_status(&self, chunk_pos: IVec3) -> Option<ChunkStatus> {
+        if self.chunks.contains_key(&chunk_pos) {
+            return Some(ChunkStatus::FINISHED);
         }
+
+        self.proto_chunks.get(&chunk_pos).map(|proto| proto.status)
+    }
+
+    /// Loads a Chunk from disk if it has been saved, otherwise starts a new one.
+    /// Doesn't need the world itself, so it can run inside a task.
+    pub fn load_or_create_chunk(region_storage: &RegionStorage, chunk_pos: IVec3) -> ProtoChunk {
+        match region_storage.load_chunk(chunk_pos) {
+            Ok(Some((chunk, status))) => ProtoChunk::new(chunk, status),
+            Ok(None) => ProtoChunk::new(Chunk::new(chunk_pos), ChunkStatus::Empty),
+            Err(err) => {
+                warn!("Could not load chunk {chunk_pos} from disk, generating it instead: {err}");
+                ProtoChunk::new(Chunk::new(chunk_pos), ChunkStatus::Empty)
+            }
+        }
+    }
+
+    /// The given loaded chunks with how far along they are, finished or not.
+    fn chunks_to_save(&self, chunk_positions: &[IVec3]) -> Vec<(Arc<Chunk>, ChunkStatus)> {
+        chunk_positions.iter().filter_map(|chunk_pos| {
+            match self.chunks.get(chunk_pos) {
+                Some(chunk) => Some((chunk.clone(), ChunkStatus::FINISHED)),
+                None => self.proto_chunks.get(chunk_pos).map(|proto| (proto.chunk.clone(), proto.status)),
+            }
+        }).collect()
+    }
+
+    /// Saves every loaded chunk that has changed since it was last saved.
+    /// Waits for the saves still running in the background first, so it's done once this returns.
+    pub fn save(&mut self) -> io::Result<()> {
+        for task in self.saving.drain(..) {
+            block_on(task);
+        }
+
+        let unsaved: Vec<IVec3> = self.unsaved.iter().copied().collect();
+        let chunks = self.chunks_to_save(&unsaved);
+        self.region_storage.save_chunks(chunks.iter().map(|(chunk, status)| (chunk.as_ref(), *status)))?;
+        self.unsaved.clear();
+
+        Ok(())
+    }
+
+    /// Saves whichever of the chunks have unsaved changes, then drops all of them.
+    /// Writing them happens on the AsyncComputeTaskPool, so this doesn't wait for the disk.
+    /// Loading them again in the meantime gets the unsaved version, see `RegionStorage::hold`.
+    pub fn unload_chunks(&mut self, chunk_positions: &[IVec3]) {
+        let unsaved: Vec<IVec3> = chunk_positions.iter()
+            .copied()
+            .filter(|chunk_pos| self.unsaved.contains(chunk_pos))
+            .collect();
+        let chunks = self.chunks_to_save(&unsaved);
+
+        for chunk_pos in chunk_positions {
+            self.chunks.remove(chunk_pos);
+            self.proto_chunks.remove(chunk_pos);
+            self.unsaved.remove(chunk_pos);
+        }
+
+        if chunks.is_empty() {
+            return;
+        }
+
+        self.saving.retain(|task| !task.is_finished());
+        self.region_storage.hold(&chunks);
+        let region_storage = self.region_storage.clone();
+        self.saving.push(AsyncComputeTaskPool::get().spawn(async move {
+            if let Err(err) = region_storage.save_held(&chunks) {
+                error!("Could not save chunks: {err}");
+            }
+        }));
     }
 }
 
-impl VoxelWorld {
-    /// Generates a Chunk using the world parameters.
-    pub fn generate_chunk(&mut self, chunk_pos: IVec3) {
-        let mut chunk = Chunk::new(chunk_pos);
+/// The noise terrain generator. Holds the noise and everything else needed to shape the terrain.
+/// Also behind the heightmap generator, which only swaps out the heights.
+#[derive(Clone)]
+pub struct TerrainGenerator {
+    pub config: WorldGenConfig,
+    pub world_noise: Fbm<Perlin>,
+    pub verticality: Perlin,
+    /// Built from `config.height_spline`.
+    pub height_spline: Spline<f64, f64>,
+    /// Replaces the noise heights and the water level of the biomes when set.
+    pub heightmap: Option<Arc<Heightmap>>,
+    pub biomes: BiomeMap,
+    pub caves: CaveCarver,
+    pub surface: SurfacePass,
+    /// Placed in order during the features stage.
+    pub features: Vec<Arc<dyn Feature>>,
+}
+
+/// The shape of a single world column.
+#[derive(Debug, Clone, Copy)]
+pub struct Column {
+    /// Height of the terrain. Everything at or below it is ground.
+    pub height: f64,
+    pub biome: ColumnBiome,
+}
+
+/// The columns of a chunk, plus a margin around it
+/// for the stages that look a little further.
+pub struct ColumnMap {
+    margin: i32,
+    columns: Vec<Column>,
+}
+
+impl ColumnMap {
+    /// The column at a local x and z. Works up to `margin` outside the chunk.
+    pub fn get(&self, x: i32, z: i32) -> Column {
+        let size = CHUNK_SIZE + self.margin * 2;
+        self.columns[((x + self.margin) + (z + self.margin) * size) as usize]
+    }
+}
+
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self::new(WorldGenConfig::default(), BiomeRegistry::default(), &OreRegistry::default())
+    }
+}
+
+impl TerrainGenerator {
+    pub fn new(config: WorldGenConfig, biomes: BiomeRegistry, ores: &OreRegistry) -> Self {
+        let seed = config.seed;
+        Self {
+            world_noise: Fbm::<Perlin>::new(seed)
+                .set_octaves(config.terrain.octaves)
+                .set_lacunarity(config.terrain.lacunarity)
+                .set_persistence(config.terrain.persistence),
+            verticality: Perlin::new(seed),
+            height_spline: linear_spline(&config.height_spline),
+            heightmap: None,
+            biomes: BiomeMap::new(seed, biomes.clone()),
+            caves: CaveCarver::new(seed, CaveSettings::default()),
+            surface: SurfacePass::default(),
+            features: vec![
+                Arc::new(OreFeature::new(seed, ores)),
+                Arc::new(VegetationFeature::new(seed, biomes)),
+            ],
+            config,
+        }
+    }
+
+    /// Shapes the terrain after a heightmap instead of the noise.
+    /// Biomes, caves and everything else still come from the config.
+    pub fn with_heightmap(config: WorldGenConfig, biomes: BiomeRegistry, ores: &OreRegistry, heightmap: Heightmap) -> Self {
+        Self {
+            heightmap: Some(Arc::new(heightmap)),
+            ..Self::new(config, biomes, ores)
+        }
+    }
+
+    /// Height and biome of a world column.
+    pub fn column(&self, wx: f64, wz: f64) -> Column {
+        let config = &self.config;
+        if let Some(heightmap) = &self.heightmap {
+            let vert = self.verticality.get([wx / config.verticality_scale, wz / config.verticality_scale]);
+            let mut biome = self.biomes.column(wx, wz, vert);
+            biome.water_level = heightmap.settings().sea_level;
+
+            return Column {
+                height: heightmap.height(wx, wz),
+                biome,
+            };
+        }
+
+        let sample = self.world_noise.get([wz / config.terrain.scale, wx / config.terrain.scale]);
+        let vert = self.verticality.get([wx / config.verticality_scale, wz / config.verticality_scale]);
+        let biome = self.biomes.column(wx, wz, vert);
+        let height_scale = biome.height_scale * self.height_spline.clamped_sample(vert).unwrap_or(1.0);
 
+        Column {
+            height: sample * config.amplitude * height_scale + config.height_offset,
+            biome,
+        }
+    }
+
+    /// Samples every column of a chunk. Caves look at the columns
+    /// around them, so this goes a little past the edges.
+    pub fn columns(&self, chunk_pos: IVec3) -> ColumnMap {
         let world_pos = chunk_pos * CHUNK_SIZE;
+        let margin = self.caves.settings.water_margin.max(0);
+        let size = CHUNK_SIZE + margin * 2;
+
+        let mut columns = Vec::with_capacity((size * size) as usize);
+        for z in -margin..CHUNK_SIZE + margin {
+            for x in -margin..CHUNK_SIZE + margin {
+                columns.push(self.column((x + world_pos.x) as f64, (z + world_pos.z) as f64));
+            }
+        }
+
+        ColumnMap { margin, columns }
+    }
+
+    /// The terrain stage. Fills everything under the surface with STONE
+    /// and everything between the surface and the water level with WATER.
+    pub fn shape_terrain(&self, chunk: &mut Chunk, columns: &ColumnMap) {
+        let world_y = chunk.position.y * CHUNK_SIZE;
 
         for x in 0..CHUNK_SIZE {
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    let wx = (x + world_pos.x) as f64;
+            for z in 0..CHUNK_SIZE {
+                let column = columns.get(x, z);
+
+                for y in 0..CHUNK_SIZE {
+                    let wy = (y + world_y) as f64;
+                    let local_pos = IVec3::new(x, y, z);
+
+                    if column.height < wy && wy <= column.biome.water_level {
+                        chunk.set(local_pos, VoxelType::WATER);
+                    } else if wy <= column.height {
+                        chunk.set(local_pos, VoxelType::STONE);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The carving stage. Digs the caves out of the STONE.
+    pub fn carve_caves(&self, chunk: &mut Chunk, columns: &ColumnMap) {
+        let world_pos = chunk.position * CHUNK_SIZE;
+        let margin = columns.margin;
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let wx = (x + world_pos.x) as f64;
+                let wz = (z + world_pos.z) as f64;
+
+                let nearby = (-margin..=margin)
+                    .flat_map(|dx| (-margin..=margin).map(move |dz| (dx, dz)))
+                    .map(|(dx, dz)| {
+                        let nearby = columns.get(x + dx, z + dz);
+                        (nearby.height, nearby.biome.water_level)
+                    });
+                let cave_ceiling = self.caves.ceiling(columns.get(x, z).height, nearby);
+
+                for y in 0..CHUNK_SIZE {
                     let wy = (y + world_pos.y) as f64;
-                    let wz = (z + world_pos.z) as f64;
-
-                    let nx = wx / ((CHUNK_SIZE_F64 * 4.0) * (CHUNK_SIZE_F64 * 0.1));
-                    let nz = wz / ((CHUNK_SIZE_F64 * 4.0) * (CHUNK_SIZE_F64 * 0.1));
-
-                    let mut sample = self.world_noise.get([nz, nx]);
-                    let vert = self.verticality.get([nx, nz]);
-                    sample *= (CHUNK_SIZE_F64 * 4.0) * self.spline_points.sample(vert).unwrap();
-
-                    let index = voxel_index(x, y, z);
-                    chunk.voxels[index].local_position = IVec3::new(x, y, z);
-                    chunk.voxels[index].world_position = IVec3::new(
-                        x + chunk.position.x * CHUNK_SIZE,
-                        y + chunk.position.y * CHUNK_SIZE,
-                        z + chunk.position.z * CHUNK_SIZE,
-                    );
-
-                    if sample < wy && wy <= 20.0 {
-                        chunk.voxels[index].voxel_type = VoxelType::WATER;
-                        continue;
+                    if wy > cave_ceiling {
+                        break;
                     }
 
-                    if wy <= sample {
-                        chunk.voxels[index].voxel_type = VoxelType::STONE;
+                    let local_pos = IVec3::new(x, y, z);
+                    if chunk.get(local_pos) == VoxelType::STONE && self.caves.is_cave(wx, wy, wz) {
+                        chunk.set(local_pos, VoxelType::AIR);
                     }
                 }
             }
         }
-
-        self.chunks.insert(chunk_pos, chunk);
     }
-}
\ No newline at end of file
+}